@@ -0,0 +1,136 @@
+// aliases - groups addresses that belong to the same node (dual stack, port forwards, several listen ports)
+// only contacted nodes take part, everything we know about a peer comes from its version message
+
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}};
+use crate::network::KnownNode;
+
+// heights two addrs of one host can differ by when we reach them a bit apart
+const HEIGHT_SLACK: i32 = 3;
+// dual stack addrs have to have been contacted this close together to be matched by fingerprint
+const DUAL_STACK_WINDOW_SECS: u64 = 120;
+
+#[derive(Default)]
+pub struct Aliases {
+    group: HashMap<SocketAddr, usize>,
+    groups: Vec<Vec<SocketAddr>>,
+}
+
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i { self.0[i] = self.0[self.0[i]]; i = self.0[i]; }
+        i
+    }
+    fn union(&mut self, a: usize, b: usize) { let (ra, rb) = (self.find(a), self.find(b)); if ra != rb { self.0[ra.max(rb)] = ra.min(rb); } }
+}
+
+fn routable(a: &SocketAddr) -> bool {
+    let ip = a.ip();
+    a.port() != 0 && !ip.is_unspecified() && !ip.is_loopback() && !matches!(ip, IpAddr::V4(v4) if v4.is_private())
+}
+
+impl Aliases {
+    pub fn find(nodes: &HashMap<SocketAddr, KnownNode>) -> Self {
+        let addrs: Vec<(&SocketAddr, &KnownNode)> = nodes.iter().filter(|(_, n)| n.user_agent.is_some()).collect();
+        let index: HashMap<SocketAddr, usize> = addrs.iter().enumerate().map(|(i, (a, _))| (**a, i)).collect();
+        let mut uf = UnionFind((0..addrs.len()).collect());
+        let ua = |i: usize| addrs[i].1.user_agent.as_ref().map(|x| x.0.as_str());
+        let height = |i: usize| addrs[i].1.start_height.unwrap_or(0);
+
+        // zcashd puts its own idea of its address into addr_from
+        for (i, (a, n)) in addrs.iter().enumerate() {
+            if let Some(from) = n.remote_addr_from.filter(|f| f != *a && routable(f)) {
+                if let Some(&j) = index.get(&from) { if ua(i) == ua(j) { uf.union(i, j); } }
+            }
+        }
+
+        // same ip on several ports with the same client and height
+        let mut by_ip: HashMap<IpAddr, Vec<usize>> = HashMap::new();
+        for (i, (a, _)) in addrs.iter().enumerate() { by_ip.entry(a.ip()).or_default().push(i); }
+        for ids in by_ip.values().filter(|v| v.len() > 1) {
+            for (k, &i) in ids.iter().enumerate() {
+                for &j in &ids[k + 1..] { if ua(i) == ua(j) && (height(i) - height(j)).abs() <= HEIGHT_SLACK { uf.union(i, j); } }
+            }
+        }
+
+        // dual stack: exactly one v4 and one v6 addr with an identical fingerprint, reached around the same time
+        let mut by_fp: HashMap<(Option<&str>, Option<u32>, u64, i32), Vec<usize>> = HashMap::new();
+        for (i, (_, n)) in addrs.iter().enumerate() {
+            by_fp.entry((ua(i), n.protocol_version.map(|v| v.0), n.services.map_or(0, |s| s.bits()), height(i))).or_default().push(i);
+        }
+        for ids in by_fp.values().filter(|v| v.len() == 2) {
+            let (i, j) = (ids[0], ids[1]);
+            if addrs[i].0.is_ipv4() == addrs[j].0.is_ipv4() { continue; }
+            let seen = |k: usize| addrs[k].1.last_connected.map(|t| t.elapsed().as_secs());
+            if let (Some(a), Some(b)) = (seen(i), seen(j)) { if a.abs_diff(b) <= DUAL_STACK_WINDOW_SECS { uf.union(i, j); } }
+        }
+
+        let mut roots: HashMap<usize, usize> = HashMap::new();
+        let mut out = Self::default();
+        for (i, (a, _)) in addrs.iter().enumerate() {
+            let r = uf.find(i);
+            let g = *roots.entry(r).or_insert_with(|| { out.groups.push(Vec::new()); out.groups.len() - 1 });
+            out.groups[g].push(**a);
+            out.group.insert(**a, g);
+        }
+        for g in out.groups.iter_mut() { g.sort(); }
+        out
+    }
+
+    // the other addrs of the same node
+    pub fn aliases_of(&self, a: &SocketAddr) -> Vec<SocketAddr> {
+        self.group.get(a).map(|g| self.groups[*g].iter().filter(|x| *x != a).copied().collect()).unwrap_or_default()
+    }
+
+    // distinct nodes behind the given addrs, ones we havent grouped count on their own
+    pub fn count_unique<'a, I: IntoIterator<Item = &'a SocketAddr>>(&self, addrs: I) -> usize {
+        let mut groups = std::collections::HashSet::new();
+        let mut loose = 0;
+        for a in addrs { match self.group.get(a) { Some(g) => { groups.insert(*g); } None => loose += 1 } }
+        groups.len() + loose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use ziggurat_zcash::protocol::payload::VarStr;
+
+    fn node(ua: &str, height: i32) -> KnownNode {
+        KnownNode { user_agent: Some(VarStr(ua.into())), start_height: Some(height), last_connected: Some(Instant::now()), ..Default::default() }
+    }
+
+    #[test]
+    fn groups_by_addr_from_ip_and_dual_stack() {
+        let mut nodes = HashMap::new();
+        let a: SocketAddr = "1.1.1.1:8233".parse().unwrap();
+        let b: SocketAddr = "1.1.1.1:18233".parse().unwrap();
+        let c: SocketAddr = "[2001:db8::1]:8233".parse().unwrap();
+        let d: SocketAddr = "2.2.2.2:8233".parse().unwrap();
+        let e: SocketAddr = "3.3.3.3:8233".parse().unwrap();
+        let f: SocketAddr = "4.4.4.4:8233".parse().unwrap();
+        nodes.insert(a, node("/MagicBean:5.10.0/", 100));
+        nodes.insert(b, node("/MagicBean:5.10.0/", 101));
+        nodes.insert(c, node("/Zebra:2.1.0/", 200));
+        nodes.insert(d, node("/Zebra:2.1.0/", 200));
+        nodes.insert(e, node("/Zebra:2.1.0/", 300));
+        // f says it is e
+        nodes.insert(f, KnownNode { remote_addr_from: Some(e), ..node("/Zebra:2.1.0/", 301) });
+
+        let al = Aliases::find(&nodes);
+        assert_eq!(al.aliases_of(&a), vec![b]);
+        assert_eq!(al.aliases_of(&c), vec![d]);
+        assert_eq!(al.aliases_of(&e), vec![f]);
+        assert_eq!(al.count_unique(nodes.keys()), 3);
+    }
+
+    #[test]
+    fn common_fingerprint_isnt_an_alias() {
+        // three nodes at the same height with the same client, cant tell which v4 goes with the v6
+        let mut nodes = HashMap::new();
+        for a in ["1.1.1.1:8233", "2.2.2.2:8233", "[2001:db8::1]:8233"] { nodes.insert(a.parse().unwrap(), node("/Zebra:2.1.0/", 200)); }
+        assert_eq!(Aliases::find(&nodes).count_unique(nodes.keys()), 3);
+    }
+}
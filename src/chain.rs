@@ -0,0 +1,280 @@
+// chain - follows the real chain tip with a header chain instead of trusting start_height
+// a few long-lived peers get getheaders from a checkpoint, their block invs trigger another round.
+// headers have to meet the target of their nbits and the tip is the branch with the most work, so a
+// peer cant move it without mining. equihash solutions and difficulty adjustment arent checked
+
+use std::{collections::HashMap, fmt, str::FromStr, time::Instant};
+use serde::Serialize;
+use ziggurat_zcash::protocol::{network::Network, payload::{block::{Header, LocatorHashes}, Hash}};
+
+// connections kept open to hear about new blocks
+pub const TIP_PEERS: usize = 4;
+// zcashd and zebra send at most this many headers per reply, a full reply means there are more
+pub const MAX_HEADERS_PER_MSG: usize = 160;
+// headers kept under the tip, reorgs deeper than this arent followed
+const KEEP_DEPTH: i32 = 2_000;
+// locator entries before the steps start doubling
+const DENSE_LOCATOR: usize = 10;
+// without a new header for this long the tip isnt trusted as live anymore, blocks come every 75s
+const STALE_AFTER_SECS: u64 = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint { pub height: i32, pub hash: Hash }
+
+impl Checkpoint {
+    pub fn genesis(network: Network) -> Self {
+        let hash = match network {
+            Network::Mainnet => "00040fe8ec8471911baa1db1266ea15dd06b4a8a5c453883c000b031973dce08",
+            Network::Testnet => "05a60a92d99d85997cce3b87616c089f6124d7342af37106edc76126334a2c38",
+            Network::Regtest => "029f11d80ef9765602235e1bc9727e3eb6ba20839319f761fee920d63401e327",
+        };
+        Self { height: 0, hash: hash.parse().expect("genesis hashes are valid hex") }
+    }
+
+    // opt-in start from a recent block so tip peers dont have to send the whole chain. taken from
+    // zebra-chain 14.0.0 src/parameters/checkpoint/{main,test}-checkpoints.txt, if one were wrong
+    // no reply would ever connect, which is why genesis stays the default
+    pub fn recent(network: Network) -> Self {
+        let (height, hash) = match network {
+            Network::Mainnet => (3_499_045, "0000000000844347b6e671b7ef18fd25772a6882e25beaf6391aaada9e0847c3"),
+            Network::Testnet => (4_414_585, "00001f89dcf98bc8e699b66a2015cec05ad36f6756c91d0e3071b2b0b225665c"),
+            Network::Regtest => return Self::genesis(network),
+        };
+        Self { height, hash: hash.parse().expect("checkpoint hashes are valid hex") }
+    }
+}
+
+// easiest target allowed on each network, as compact nbits
+fn pow_limit(network: Network) -> u32 {
+    match network { Network::Mainnet => 0x1f07ffff, Network::Testnet => 0x2007ffff, Network::Regtest => 0x200f0f0f }
+}
+
+// compact nbits to a big endian 256 bit target, None for negative, zero or overflowing ones
+fn target(bits: u32) -> Option<[u8; 32]> {
+    let (exp, mantissa) = ((bits >> 24) as usize, bits & 0x007f_ffff);
+    if bits & 0x0080_0000 != 0 || mantissa == 0 || !(3..=32).contains(&exp) { return None; }
+    let mut t = [0u8; 32];
+    t[32 - exp..35 - exp].copy_from_slice(&mantissa.to_be_bytes()[1..]);
+    Some(t)
+}
+
+// roughly 2^256 / target, what a header adds to the work of its branch
+fn work(bits: u32) -> u128 {
+    let (exp, mantissa) = ((bits >> 24) as u32, (bits & 0x007f_ffff) as u128);
+    let shift = 256 - 8 * (exp - 3);
+    if shift < 128 { return (1u128 << shift) / mantissa; }
+    ((1u128 << 127) / mantissa).checked_mul(1u128 << (shift - 127).min(127)).unwrap_or(u128::MAX)
+}
+
+// the hash is little endian on the wire, the target big endian
+fn meets_target(hash: &Hash, bits: u32, network: Network) -> bool {
+    let (t, limit) = match (target(bits), target(pow_limit(network))) { (Some(t), Some(l)) => (t, l), _ => return false };
+    let mut h = *hash.as_bytes();
+    h.reverse();
+    t <= limit && h <= t
+}
+
+// height:hash, as passed to --checkpoint
+impl FromStr for Checkpoint {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (h, hash) = s.split_once(':').ok_or("expected <height>:<hash>")?;
+        Ok(Self { height: h.trim().parse().map_err(|_| format!("bad height {:?}", h))?, hash: hash.trim().parse()? })
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}:{}", self.height, self.hash) }
+}
+
+// what gettip returns
+#[derive(Clone, Serialize)]
+pub struct TipInfo {
+    pub height: i32,
+    pub hash: String,
+    // false while we are still catching up from the checkpoint, the tip isnt live yet
+    pub synced: bool,
+    // work of the tip branch since the oldest header kept
+    pub work: String,
+    pub last_update_secs: Option<u64>,
+    pub headers_kept: usize,
+}
+
+// work is cumulative from the base
+struct Entry { height: i32, prev: Hash, work: u128 }
+
+pub struct HeaderChain {
+    network: Network,
+    headers: HashMap<Hash, Entry>,
+    // lowest header we keep, everything in the map descends from it
+    base: Checkpoint,
+    tip: Checkpoint,
+    synced: bool,
+    updated: Option<Instant>,
+}
+
+impl HeaderChain {
+    pub fn new(cp: Checkpoint, network: Network) -> Self {
+        let mut headers = HashMap::new();
+        headers.insert(cp.hash, Entry { height: cp.height, prev: Hash::zeroed(), work: 0 });
+        Self { network, headers, base: cp, tip: cp, synced: false, updated: None }
+    }
+
+    pub fn tip(&self) -> Checkpoint { self.tip }
+    pub fn is_synced(&self) -> bool { self.synced }
+    pub fn contains(&self, h: &Hash) -> bool { self.headers.contains_key(h) }
+    pub fn height_of(&self, h: &Hash) -> Option<i32> { self.headers.get(h).map(|e| e.height) }
+
+    // the tip height if its live, what the classifier uses instead of the start_height percentile
+    pub fn live_height(&self) -> Option<i32> { self.is_live().then_some(self.tip.height) }
+
+    fn is_live(&self) -> bool { self.synced && self.updated.map_or(false, |t| t.elapsed().as_secs() < STALE_AFTER_SECS) }
+
+    fn tip_work(&self) -> u128 { self.headers.get(&self.tip.hash).map_or(0, |e| e.work) }
+
+    pub fn info(&self) -> TipInfo {
+        TipInfo {
+            height: self.tip.height, hash: self.tip.hash.to_string(), synced: self.is_live(), work: self.tip_work().to_string(),
+            last_update_secs: self.updated.map(|t| t.elapsed().as_secs()), headers_kept: self.headers.len(),
+        }
+    }
+
+    // newest first, dense at the tip then doubling steps, always ending at the base
+    pub fn locator(&self) -> LocatorHashes {
+        let mut out = Vec::new();
+        let mut cur = self.tip.hash;
+        let mut step = 1;
+        while cur != self.base.hash {
+            out.push(cur);
+            if out.len() >= DENSE_LOCATOR { step *= 2; }
+            for _ in 0..step {
+                if cur == self.base.hash { break; }
+                match self.headers.get(&cur) { Some(e) => cur = e.prev, None => { cur = self.base.hash; break; } }
+            }
+        }
+        out.push(self.base.hash);
+        LocatorHashes::new(out, Hash::zeroed())
+    }
+
+    // adds the headers that connect to what we have and carry their work, returns (new, all connected).
+    // the tip moves to whichever branch has the most work, the first one seen on a tie
+    pub fn add_headers(&mut self, headers: &[Header]) -> (usize, bool) {
+        let (mut added, mut connected) = (0, true);
+        for h in headers {
+            let hash = match h.double_sha256() { Ok(x) => x, Err(_) => { connected = false; continue } };
+            if self.headers.contains_key(&hash) { continue; }
+            if !meets_target(&hash, h.bits, self.network) { connected = false; continue; }
+            let (height, total) = match self.headers.get(&h.prev_block) { Some(p) => (p.height + 1, p.work.saturating_add(work(h.bits))), None => { connected = false; continue; } };
+            self.headers.insert(hash, Entry { height, prev: h.prev_block, work: total });
+            if total > self.tip_work() { self.tip = Checkpoint { height, hash }; }
+            added += 1;
+        }
+        if added > 0 { self.updated = Some(Instant::now()); self.prune(); }
+        (added, connected)
+    }
+
+    // a getheaders reply from a peer that advertised peer_height. once a reply that all fits our chain gets
+    // us to that height weve caught up, thats when the tip counts as live
+    pub fn add_reply(&mut self, headers: &[Header], peer_height: i32) -> usize {
+        let (added, connected) = self.add_headers(headers);
+        if !headers.is_empty() && connected && self.tip.height >= peer_height {
+            if !self.synced { self.updated = Some(Instant::now()); }
+            self.synced = true;
+        }
+        added
+    }
+
+    fn prune(&mut self) {
+        if self.tip.height - self.base.height <= 2 * KEEP_DEPTH { return; }
+        let mut cur = self.tip.hash;
+        while let Some(e) = self.headers.get(&cur) {
+            if e.height <= self.tip.height - KEEP_DEPTH { break; }
+            cur = e.prev;
+        }
+        let height = match self.headers.get(&cur) { Some(e) => e.height, None => return };
+        self.headers.retain(|_, e| e.height >= height);
+        self.base = Checkpoint { height, hash: cur };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASY: u32 = 0x2007ffff;
+    // 16 times the work of EASY
+    const HARD: u32 = 0x1f7fffff;
+
+    // grinds the nonce until the header meets its target
+    fn mine(prev: Hash, bits: u32, salt: u8) -> Header {
+        let mut h = ziggurat_zcash::protocol::payload::block::Block::testnet_genesis().header;
+        h.prev_block = prev;
+        h.bits = bits;
+        h.nonce[0] = salt;
+        for i in 0..=u16::MAX {
+            h.nonce[1..3].copy_from_slice(&i.to_le_bytes());
+            if meets_target(&h.double_sha256().unwrap(), bits, Network::Testnet) { return h; }
+        }
+        panic!("no nonce found");
+    }
+
+    fn chain_of(start: Hash, len: usize, bits: u32, salt: u8) -> Vec<Header> {
+        let mut out: Vec<Header> = Vec::new();
+        let mut prev = start;
+        for i in 0..len { let h = mine(prev, bits, salt.wrapping_add(i as u8)); prev = h.double_sha256().unwrap(); out.push(h); }
+        out
+    }
+
+    #[test]
+    fn follows_the_most_work() {
+        let cp = Checkpoint::genesis(Network::Testnet);
+        assert_eq!(cp.to_string().parse::<Checkpoint>().unwrap(), cp);
+        assert_eq!(work(HARD), 16 * work(EASY));
+        let mut chain = HeaderChain::new(cp, Network::Testnet);
+        let main = chain_of(cp.hash, 5, EASY, 0);
+        // short of what the peer advertised, still catching up
+        assert_eq!(chain.add_reply(&main[..3], 5), 3);
+        assert!(!chain.is_synced());
+        assert_eq!(chain.tip().height, 3);
+
+        // headers that dont connect are dropped
+        assert_eq!(chain.add_headers(&main[4..]), (0, false));
+        assert_eq!(chain.add_reply(&main[3..], 5), 2);
+        assert!(chain.is_synced());
+        assert_eq!(chain.live_height(), Some(5));
+        assert_eq!(chain.tip(), Checkpoint { height: 5, hash: main[4].double_sha256().unwrap() });
+
+        // a longer fork from height 2 takes over
+        let fork = chain_of(main[1].double_sha256().unwrap(), 4, EASY, 100);
+        chain.add_headers(&fork);
+        assert_eq!(chain.tip().height, 6);
+        let loc = chain.locator().block_locator_hashes;
+        assert_eq!(loc.first(), Some(&fork[3].double_sha256().unwrap()));
+        assert_eq!(loc.last(), Some(&cp.hash));
+
+        // a shorter branch with more work takes over too, height alone doesnt count
+        let heavy = mine(main[3].double_sha256().unwrap(), HARD, 200);
+        assert_eq!(chain.add_headers(&[heavy.clone()]), (1, true));
+        assert_eq!(chain.tip(), Checkpoint { height: 5, hash: heavy.double_sha256().unwrap() });
+    }
+
+    #[test]
+    fn rejects_unmined_headers_and_empty_replies() {
+        let cp = Checkpoint::genesis(Network::Testnet);
+        let mut chain = HeaderChain::new(cp, Network::Testnet);
+        // an empty reply or a lone pushed header doesnt make the chain live
+        assert_eq!(chain.add_reply(&[], 0), 0);
+        assert_eq!(chain.add_headers(&[mine(cp.hash, EASY, 0)]), (1, true));
+        assert!(!chain.is_synced());
+
+        let tip = chain.tip().hash;
+        // a target no hash meets, and one easier than the network allows
+        for bits in [0x03000001, 0x2100ffff] {
+            let mut h = mine(tip, EASY, 1);
+            h.bits = bits;
+            assert_eq!(chain.add_headers(&[h]), (0, false));
+        }
+        assert_eq!(chain.tip().height, 1);
+        assert!(Checkpoint::recent(Network::Mainnet).height > 0);
+    }
+}
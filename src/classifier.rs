@@ -0,0 +1,260 @@
+// node classifier - one place that decides what client a node runs and whether it counts as a relevant zcash node
+// rules come from a toml file, see classifier.toml for the defaults and what each field does
+
+use std::{collections::HashMap, fmt, fs, net::SocketAddr, path::Path, sync::Arc};
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use ziggurat_zcash::protocol::network::Network;
+use crate::{chain::HeaderChain, network::{KnownNode, UptimeWindow}};
+
+pub const DEFAULT_RULES: &str = include_str!("../classifier.toml");
+pub const OTHER_CLIENT: &str = "other";
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortRule { Any, Default }
+
+#[derive(Clone, Deserialize)]
+pub struct MinHeight { pub mainnet: i32, pub testnet: i32, pub regtest: i32 }
+
+#[derive(Clone, Deserialize)]
+pub struct ClientRule {
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub min_version: Option<String>,
+    #[serde(default)]
+    pub max_version: Option<String>,
+    #[serde(default)]
+    pub fork: Option<String>,
+    #[serde(default)]
+    pub check_tip: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Rules {
+    pub port: PortRule,
+    pub height_tolerance: i32,
+    pub min_height: MinHeight,
+    #[serde(rename = "client")]
+    pub clients: Vec<ClientRule>,
+}
+
+impl Default for Rules {
+    fn default() -> Self { toml::from_str(DEFAULT_RULES).expect("bundled classifier rules are valid") }
+}
+
+impl Rules {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        toml::from_str(&fs::read_to_string(path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+    }
+}
+
+type Version = (u32, u32, u32);
+
+fn parse_version(s: &str) -> Option<Version> {
+    let mut p = s.trim().split('.').map(|x| x.parse::<u32>());
+    Some((p.next()?.ok()?, p.next().unwrap_or(Ok(0)).ok()?, p.next().unwrap_or(Ok(0)).ok()?))
+}
+
+struct CompiledRule { rule: ClientRule, re: Regex, min: Option<Version>, max: Option<Version> }
+
+// why a node is or isnt relevant, the first failing check wins
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Relevant,
+    NoUserAgent,
+    UnknownClient,
+    Fork(String),
+    WrongPort(u16),
+    BelowMinHeight { height: i32, min: i32 },
+    OffTip { height: i32, tip: i32 },
+    LowUptime { window: UptimeWindow, score: f64, min: f64 },
+}
+
+impl Verdict {
+    // stable short name, used as a key when counting
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Relevant => "relevant", Self::NoUserAgent => "no_user_agent", Self::UnknownClient => "unknown_client",
+            Self::Fork(_) => "fork", Self::WrongPort(_) => "wrong_port", Self::BelowMinHeight { .. } => "below_min_height",
+            Self::OffTip { .. } => "off_tip", Self::LowUptime { .. } => "low_uptime",
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Relevant => write!(f, "passed all filters"),
+            Self::NoUserAgent => write!(f, "never completed a version exchange"),
+            Self::UnknownClient => write!(f, "user agent matches no client rule"),
+            Self::Fork(name) => write!(f, "runs the {} fork", name),
+            Self::WrongPort(p) => write!(f, "listens on {} instead of the default port", p),
+            Self::BelowMinHeight { height, min } => write!(f, "height {} is below the minimum {}", height, min),
+            Self::OffTip { height, tip } => write!(f, "height {} is more than the tolerance away from tip {}", height, tip),
+            Self::LowUptime { window, score, min } => write!(f, "{} uptime {:.2} is below {:.2}", window.name(), score, min),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub client: String,
+    pub fork: Option<String>,
+    pub verdict: Verdict,
+}
+
+impl Classification {
+    pub fn is_relevant(&self) -> bool { self.verdict == Verdict::Relevant }
+
+    // None when the node passed, otherwise why it didnt
+    pub fn reason(&self) -> Option<String> { if self.is_relevant() { None } else { Some(self.verdict.to_string()) } }
+}
+
+// per-verdict counts over a node set, what getdiagnostics reports
+#[derive(Clone, Default, Serialize)]
+pub struct VerdictCounts { pub by_reason: HashMap<&'static str, usize>, pub by_client: HashMap<String, usize> }
+
+pub struct NodeClassifier {
+    rules: Rules,
+    compiled: Vec<CompiledRule>,
+    network: Network,
+    // when set and synced, the tip comes from headers instead of what nodes report
+    chain: Option<Arc<RwLock<HeaderChain>>>,
+}
+
+impl NodeClassifier {
+    pub fn new(rules: Rules, network: Network) -> Result<Self, String> {
+        let mut compiled = Vec::with_capacity(rules.clients.len());
+        for r in &rules.clients {
+            let re = Regex::new(&r.pattern).map_err(|e| format!("client {}: {}", r.name, e))?;
+            let ver = |v: &Option<String>| v.as_deref().map(|s| parse_version(s).ok_or_else(|| format!("client {}: bad version {:?}", r.name, s))).transpose();
+            compiled.push(CompiledRule { rule: r.clone(), re, min: ver(&r.min_version)?, max: ver(&r.max_version)? });
+        }
+        Ok(Self { rules, compiled, network, chain: None })
+    }
+
+    pub fn with_chain(mut self, chain: Arc<RwLock<HeaderChain>>) -> Self { self.chain = Some(chain); self }
+
+    pub fn network(&self) -> Network { self.network }
+
+    pub fn min_height(&self) -> i32 {
+        let m = &self.rules.min_height;
+        match self.network { Network::Mainnet => m.mainnet, Network::Testnet => m.testnet, Network::Regtest => m.regtest }
+    }
+
+    // client names the rules can produce, including the fallback
+    pub fn client_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.compiled.iter().map(|c| c.rule.name.clone()).collect();
+        names.push(OTHER_CLIENT.into());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn match_rule(&self, ua: &str) -> Option<&ClientRule> {
+        self.compiled.iter().find(|c| {
+            let caps = match c.re.captures(ua) { Some(c) => c, None => return false };
+            if c.min.is_none() && c.max.is_none() { return true; }
+            let num = |i| caps.get(i).and_then(|m| m.as_str().parse::<u32>().ok()).unwrap_or(0);
+            let v = (num(1), num(2), num(3));
+            c.min.map_or(true, |m| v >= m) && c.max.map_or(true, |m| v < m)
+        }).map(|c| &c.rule)
+    }
+
+    // the header chain tip once its synced, before that the 95th percentile of reported heights above the
+    // minimum, so a few nodes lying about their height dont move it
+    pub fn tip(&self, nodes: &HashMap<SocketAddr, KnownNode>) -> i32 {
+        if let Some(h) = self.chain.as_ref().and_then(|c| c.read().live_height()) { return h; }
+        let min = self.min_height();
+        let mut h: Vec<_> = nodes.values().filter_map(|n| n.start_height).filter(|x| *x > min).collect();
+        if h.is_empty() { return min; }
+        h.sort();
+        h.get((h.len() as f64 * 0.95) as usize).copied().unwrap_or(min)
+    }
+
+    // min_uptime is an optional (window, fraction of successful connects) the node has to reach
+    pub fn classify(&self, addr: &SocketAddr, n: &KnownNode, tip: i32, min_uptime: Option<(UptimeWindow, f64)>) -> Classification {
+        let ua = match &n.user_agent {
+            Some(ua) => &ua.0,
+            None => return Classification { client: OTHER_CLIENT.into(), fork: None, verdict: Verdict::NoUserAgent },
+        };
+        let rule = self.match_rule(ua);
+        let (client, fork) = rule.map_or((OTHER_CLIENT.into(), None), |r| (r.name.clone(), r.fork.clone()));
+        let h = n.start_height.unwrap_or(0);
+        let verdict = match rule {
+            None => Verdict::UnknownClient,
+            Some(r) if r.fork.is_some() => Verdict::Fork(r.fork.clone().unwrap_or_default()),
+            _ if self.rules.port == PortRule::Default && addr.port() != self.network.default_port() => Verdict::WrongPort(addr.port()),
+            _ if h < self.min_height() => Verdict::BelowMinHeight { height: h, min: self.min_height() },
+            Some(r) if r.check_tip && (h - tip).abs() > self.rules.height_tolerance => Verdict::OffTip { height: h, tip },
+            _ => match min_uptime {
+                Some((window, min)) if n.reachability.score(window) < min => Verdict::LowUptime { window, score: n.reachability.score(window), min },
+                _ => Verdict::Relevant,
+            },
+        };
+        Classification { client, fork, verdict }
+    }
+
+    pub fn is_good_node(&self, addr: &SocketAddr, n: &KnownNode, tip: i32, min_uptime: Option<(UptimeWindow, f64)>) -> bool {
+        self.classify(addr, n, tip, min_uptime).is_relevant()
+    }
+
+    pub fn count(&self, nodes: &HashMap<SocketAddr, KnownNode>) -> VerdictCounts {
+        let tip = self.tip(nodes);
+        let mut out = VerdictCounts::default();
+        for (a, n) in nodes {
+            let c = self.classify(a, n, tip, None);
+            *out.by_reason.entry(c.verdict.key()).or_default() += 1;
+            if c.verdict != Verdict::NoUserAgent { *out.by_client.entry(c.client).or_default() += 1; }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ziggurat_zcash::protocol::payload::VarStr;
+
+    fn node(ua: &str, height: i32) -> KnownNode {
+        KnownNode { user_agent: Some(VarStr(ua.into())), start_height: Some(height), ..Default::default() }
+    }
+
+    #[test]
+    fn default_rules_classify_clients() {
+        let c = NodeClassifier::new(Rules::default(), Network::Mainnet).unwrap();
+        let a: SocketAddr = "1.2.3.4:8233".parse().unwrap();
+        let tip = 3_000_000;
+        let v = |ua, h| c.classify(&a, &node(ua, h), tip, None);
+
+        assert_eq!(v("/MagicBean:5.10.0/", 2_999_000).verdict, Verdict::Relevant);
+        assert_eq!(v("/MagicBean:5.10.0/", 2_999_000).client, "zcashd");
+        // zcashd 6.x is nu6 zcashd, flux is only told apart by its own marker
+        assert_eq!(v("/MagicBean:6.1.0/", 2_999_000).client, "zcashd");
+        assert_eq!(v("/MagicBean:6.1.0/", 2_999_000).verdict, Verdict::Relevant);
+        assert_eq!(v("/MagicBean:5.4.2(flux)/", 2_999_000).client, "flux");
+        assert_eq!(v("/Zebra:2.1.0/", 2_999_000).verdict, Verdict::Relevant);
+        assert_eq!(v("/Zebra:2.1.0/", 2_900_000).verdict, Verdict::OffTip { height: 2_900_000, tip });
+        // zcashd doesnt get the tip check, it reports its height at connect time
+        assert_eq!(v("/MagicBean:5.10.0/", 2_900_000).verdict, Verdict::Relevant);
+        assert_eq!(v("/Zebra:2.1.0/", 1_000).verdict, Verdict::BelowMinHeight { height: 1_000, min: 2_500_000 });
+        assert_eq!(v("/Satoshi:25.0.0/", 2_999_000).verdict, Verdict::UnknownClient);
+        assert_eq!(c.classify(&a, &KnownNode::default(), tip, None).verdict, Verdict::NoUserAgent);
+    }
+
+    #[test]
+    fn port_and_version_rules() {
+        let mut rules = Rules::default();
+        rules.port = PortRule::Default;
+        rules.clients.insert(0, ClientRule { name: "zcashd".into(), pattern: r"^/MagicBean:(\d+)\.(\d+)\.(\d+)".into(), min_version: None,
+            max_version: Some("5.0".into()), fork: Some("old".into()), check_tip: false });
+        let c = NodeClassifier::new(rules, Network::Mainnet).unwrap();
+        let n = node("/MagicBean:5.10.0/", 2_999_000);
+        assert_eq!(c.classify(&"1.2.3.4:18233".parse().unwrap(), &n, 3_000_000, None).verdict, Verdict::WrongPort(18233));
+        assert_eq!(c.classify(&"1.2.3.4:8233".parse().unwrap(), &node("/MagicBean:4.7.0/", 2_999_000), 3_000_000, None).verdict, Verdict::Fork("old".into()));
+        assert!(NodeClassifier::new(Rules { clients: vec![ClientRule { name: "x".into(), pattern: "(".into(), min_version: None, max_version: None, fork: None, check_tip: false }], ..Rules::default() }, Network::Mainnet).is_err());
+    }
+}
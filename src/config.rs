@@ -0,0 +1,247 @@
+// crawler config - the tunables that used to be compile time constants, read from a toml file with cli
+// overrides on top, see znodes.toml for every field with its default. together with the classifier rules
+// they make up the settings, which can be reloaded on a running crawler with SIGHUP or the reloadconfig rpc
+
+use std::{fs, path::{Path, PathBuf}, sync::Arc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use ziggurat_zcash::protocol::network::Network;
+use crate::{chain::HeaderChain, classifier::{NodeClassifier, Rules}, scheduler::RetryPolicy};
+
+// longest user agent zcashd accepts in a version message
+const MAX_USER_AGENT_LEN: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcAccess {
+    // needed by reloadconfig, which is off while unset, never shown by getconfig
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+    // answered with an error instead
+    pub disabled_methods: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig {
+    // dns names or addrs, resolved again every dns_refresh_secs
+    pub seeds: Vec<String>,
+    pub crawl_interval_secs: u64,
+    pub max_concurrent_connections: u16,
+    pub max_wait_for_addr_secs: u64,
+    pub last_seen_cutoff_secs: u64,
+    pub summary_interval_secs: u64,
+    pub dns_refresh_secs: u64,
+    pub user_agent: String,
+    // advertised in our version message, the header chain tip if not set
+    pub start_height: Option<i32>,
+    // replace the classifier rules values for the selected network when set
+    pub height_tolerance: Option<i32>,
+    pub min_height: Option<i32>,
+    pub retry: RetryPolicy,
+    pub rpc: RpcAccess,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            seeds: Vec::new(), crawl_interval_secs: 10, max_concurrent_connections: 3500, max_wait_for_addr_secs: 90,
+            last_seen_cutoff_secs: 600, summary_interval_secs: 60, dns_refresh_secs: 120,
+            // pretend to be zcashd 5.4.2
+            user_agent: "/MagicBean:5.4.2/".into(), start_height: None, height_tolerance: None, min_height: None, retry: RetryPolicy::default(), rpc: RpcAccess::default(),
+        }
+    }
+}
+
+// every config field as a flag, a flag that is given wins over the file
+#[derive(clap::Args, Clone, Default)]
+pub struct ConfigOverrides {
+    /// dns seeds or node addrs to start from, replaces the seeds of the config file
+    #[clap(short, long, value_parser, num_args(1..))]
+    pub seed_addrs: Option<Vec<String>>,
+    /// secs between crawl rounds
+    #[clap(short, long, value_parser)]
+    pub crawl_interval: Option<u64>,
+    /// cap on open plus pending connections
+    #[clap(long, value_parser)]
+    pub max_concurrent_connections: Option<u16>,
+    /// secs a crawled connection is kept open waiting for addrs
+    #[clap(long, value_parser)]
+    pub max_wait_for_addr_secs: Option<u64>,
+    /// secs after which an unrefreshed addr gossip edge is dropped
+    #[clap(long, value_parser)]
+    pub last_seen_cutoff_secs: Option<u64>,
+    #[clap(long, value_parser)]
+    pub summary_interval_secs: Option<u64>,
+    /// secs between dns seed lookups
+    #[clap(long, value_parser)]
+    pub dns_refresh_secs: Option<u64>,
+    /// user agent sent in our version message
+    #[clap(long, value_parser)]
+    pub user_agent: Option<String>,
+    /// start_height sent in our version message, the header chain tip if not set
+    #[clap(long, value_parser)]
+    pub start_height: Option<i32>,
+    /// overrides height_tolerance from the classifier rules
+    #[clap(long, value_parser)]
+    pub height_tolerance: Option<i32>,
+    /// overrides the classifier rules min_height of the selected network
+    #[clap(long, value_parser)]
+    pub min_height: Option<i32>,
+    /// wait between attempts to a node, doubled for every consecutive failure
+    #[clap(long, value_parser)]
+    pub retry_base_secs: Option<u64>,
+    /// upper bound for the failure backoff
+    #[clap(long, value_parser)]
+    pub retry_max_secs: Option<u64>,
+    /// forget addresses without a successful connection for this many days, 0 keeps them forever
+    #[clap(long, value_parser)]
+    pub evict_after_days: Option<u64>,
+    /// connection attempts started per crawl round
+    #[clap(long, value_parser)]
+    pub max_attempts_per_round: Option<usize>,
+}
+
+impl CrawlerConfig {
+    pub fn parse(s: &str) -> Result<Self, String> { toml::from_str(s).map_err(|e| e.to_string()) }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn apply(&mut self, o: &ConfigOverrides) {
+        fn set<T: Clone>(dst: &mut T, v: &Option<T>) { if let Some(v) = v { *dst = v.clone(); } }
+        set(&mut self.seeds, &o.seed_addrs);
+        set(&mut self.crawl_interval_secs, &o.crawl_interval);
+        set(&mut self.max_concurrent_connections, &o.max_concurrent_connections);
+        set(&mut self.max_wait_for_addr_secs, &o.max_wait_for_addr_secs);
+        set(&mut self.last_seen_cutoff_secs, &o.last_seen_cutoff_secs);
+        set(&mut self.summary_interval_secs, &o.summary_interval_secs);
+        set(&mut self.dns_refresh_secs, &o.dns_refresh_secs);
+        set(&mut self.user_agent, &o.user_agent);
+        if o.start_height.is_some() { self.start_height = o.start_height; }
+        if o.height_tolerance.is_some() { self.height_tolerance = o.height_tolerance; }
+        if o.min_height.is_some() { self.min_height = o.min_height; }
+        set(&mut self.retry.retry_base_secs, &o.retry_base_secs);
+        set(&mut self.retry.retry_max_secs, &o.retry_max_secs);
+        set(&mut self.retry.evict_after_days, &o.evict_after_days);
+        set(&mut self.retry.max_attempts_per_round, &o.max_attempts_per_round);
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, v) in [("crawl_interval_secs", self.crawl_interval_secs), ("max_wait_for_addr_secs", self.max_wait_for_addr_secs),
+            ("last_seen_cutoff_secs", self.last_seen_cutoff_secs), ("summary_interval_secs", self.summary_interval_secs),
+            ("dns_refresh_secs", self.dns_refresh_secs), ("retry.retry_base_secs", self.retry.retry_base_secs)] {
+            if v == 0 { return Err(format!("{} must be above 0", name)); }
+        }
+        if self.max_concurrent_connections == 0 { return Err("max_concurrent_connections must be above 0".into()); }
+        if self.retry.max_attempts_per_round == 0 { return Err("retry.max_attempts_per_round must be above 0".into()); }
+        if self.retry.retry_max_secs < self.retry.retry_base_secs { return Err("retry.retry_max_secs is below retry.retry_base_secs".into()); }
+        if self.user_agent.is_empty() || self.user_agent.len() > MAX_USER_AGENT_LEN { return Err(format!("user_agent must be 1 to {} bytes", MAX_USER_AGENT_LEN)); }
+        for (name, v) in [("start_height", self.start_height), ("height_tolerance", self.height_tolerance), ("min_height", self.min_height)] {
+            if v.map_or(false, |v| v < 0) { return Err(format!("{} cant be negative", name)); }
+        }
+        if self.rpc.admin_token.as_deref() == Some("") { return Err("rpc.admin_token cant be empty, leave it out to disable reloadconfig".into()); }
+        Ok(())
+    }
+
+    // height settings given here win over the classifier rules file
+    pub fn apply_to_rules(&self, rules: &mut Rules, network: Network) {
+        if let Some(t) = self.height_tolerance { rules.height_tolerance = t; }
+        if let Some(h) = self.min_height {
+            match network { Network::Mainnet => rules.min_height.mainnet = h, Network::Testnet => rules.min_height.testnet = h, Network::Regtest => rules.min_height.regtest = h }
+        }
+    }
+}
+
+// everything a reload can change, swapped as a whole so readers never see half old and half new settings
+pub struct Settings {
+    pub config: CrawlerConfig,
+    pub classifier: Arc<NodeClassifier>,
+}
+
+// where the settings come from, a reload reads the same files again and reapplies the same flags
+pub struct SettingsSource {
+    pub config: Option<PathBuf>,
+    pub overrides: ConfigOverrides,
+    pub rules: Option<PathBuf>,
+    pub network: Network,
+    pub chain: Arc<RwLock<HeaderChain>>,
+}
+
+impl SettingsSource {
+    pub fn load(&self) -> Result<Settings, String> {
+        let mut config = match self.config.as_ref() {
+            Some(p) => CrawlerConfig::load(p).map_err(|e| format!("cant read config {}: {}", p.display(), e))?,
+            None => CrawlerConfig::default(),
+        };
+        config.apply(&self.overrides);
+        config.validate().map_err(|e| format!("bad config: {}", e))?;
+        let mut rules = self.rules.as_ref().map_or_else(|| Ok(Rules::default()), Rules::load).map_err(|e| format!("bad classifier rules: {}", e))?;
+        config.apply_to_rules(&mut rules, self.network);
+        let classifier = NodeClassifier::new(rules, self.network).map_err(|e| format!("bad classifier rules: {}", e))?.with_chain(Arc::clone(&self.chain));
+        Ok(Settings { config, classifier: Arc::new(classifier) })
+    }
+}
+
+// readers take a snapshot with get, the lock is only held to clone the arc
+#[derive(Clone)]
+pub struct SharedSettings { current: Arc<RwLock<Arc<Settings>>>, source: Arc<SettingsSource> }
+
+impl SharedSettings {
+    pub fn open(source: SettingsSource) -> Result<Self, String> {
+        Ok(Self { current: Arc::new(RwLock::new(Arc::new(source.load()?))), source: Arc::new(source) })
+    }
+
+    pub fn get(&self) -> Arc<Settings> { Arc::clone(&self.current.read()) }
+
+    // on any error the running settings stay as they are
+    pub fn reload(&self) -> Result<Arc<Settings>, String> {
+        let s = Arc::new(self.source.load()?);
+        *self.current.write() = Arc::clone(&s);
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_file_overrides_and_validation() {
+        // the documented example is exactly the defaults
+        assert_eq!(CrawlerConfig::load(concat!(env!("CARGO_MANIFEST_DIR"), "/znodes.toml")).unwrap(), CrawlerConfig::default());
+
+        let mut c = CrawlerConfig::parse("crawl_interval_secs = 30\n[retry]\nevict_after_days = 3\n").unwrap();
+        assert_eq!((c.crawl_interval_secs, c.retry.evict_after_days, c.retry.retry_base_secs), (30, 3, 45));
+        c.apply(&ConfigOverrides { crawl_interval: Some(5), min_height: Some(100), ..Default::default() });
+        assert_eq!((c.crawl_interval_secs, c.min_height), (5, Some(100)));
+        assert!(c.validate().is_ok());
+
+        assert!(CrawlerConfig::parse("crawl_intervl_secs = 30").is_err());
+        c.retry.retry_max_secs = 10;
+        assert!(c.validate().unwrap_err().contains("retry_max_secs"));
+    }
+
+    #[test]
+    fn reload_swaps_or_keeps() {
+        let path = std::env::temp_dir().join(format!("znodes-config-{}.toml", std::process::id()));
+        fs::write(&path, "seeds = [\"a.example\"]\nheight_tolerance = 5\n").unwrap();
+        let chain = Arc::new(RwLock::new(HeaderChain::new(crate::chain::Checkpoint::genesis(Network::Mainnet), Network::Mainnet)));
+        let source = SettingsSource { config: Some(path.clone()), overrides: ConfigOverrides::default(), rules: None, network: Network::Mainnet, chain };
+        let s = SharedSettings::open(source).unwrap();
+        let old = s.get();
+        assert_eq!(old.config.seeds, vec!["a.example".to_string()]);
+
+        fs::write(&path, "seeds = [\"b.example\"]\nmax_concurrent_connections = 100\n").unwrap();
+        s.reload().unwrap();
+        assert_eq!((s.get().config.seeds.clone(), s.get().config.max_concurrent_connections), (vec!["b.example".to_string()], 100));
+        // snapshots taken before the reload keep the old values
+        assert_eq!(old.config.seeds, vec!["a.example".to_string()]);
+
+        fs::write(&path, "max_concurrent_connections = 0\n").unwrap();
+        assert!(s.reload().is_err());
+        assert_eq!(s.get().config.max_concurrent_connections, 100);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,253 @@
+// dns seeder - small authoritative dns server answering A/AAAA with good nodes
+// x<hex>.<zone> only returns nodes that have all of those service bits set, like the bitcoin seeder
+
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, sync::Arc, time::{Duration, Instant}};
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info};
+use crate::{config::SharedSettings, network::{unix_now, KnownNode, UptimeWindow}};
+
+const MAX_A_ANSWERS: usize = 25;
+// keeps the reply under 512 bytes without edns
+const MAX_AAAA_ANSWERS: usize = 15;
+const ADDR_TTL: u32 = 60;
+const NS_TTL: u32 = 40_000;
+const CACHE_REFRESH: Duration = Duration::from_secs(60);
+// answers should be nodes that stay up, not whoever we reached once
+const MIN_UPTIME: (UptimeWindow, f64) = (UptimeWindow::D1, 0.5);
+
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+// plain udp without edns, records that dont fit are dropped and the reply marked truncated
+const MAX_UDP_LEN: usize = 512;
+// name pointer, type, class, ttl and rdata length
+const RECORD_OVERHEAD: usize = 12;
+
+const RCODE_NOERROR: u8 = 0;
+const RCODE_FORMERR: u8 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_NOTIMP: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+
+#[derive(Clone)]
+pub struct SeederConfig {
+    pub zone: String,
+    // name of this server, used for the NS record and as SOA mname
+    pub ns: String,
+    // SOA rname, e.g. hostmaster.example.com
+    pub mbox: String,
+}
+
+struct Question { name: String, qtype: u16, qclass: u16, end: usize }
+
+fn parse_question(pkt: &[u8]) -> Option<Question> {
+    let mut labels = Vec::new();
+    let mut i = 12;
+    loop {
+        let len = *pkt.get(i)? as usize;
+        i += 1;
+        if len == 0 { break; }
+        // no compression in questions
+        if len > 63 { return None; }
+        labels.push(String::from_utf8_lossy(pkt.get(i..i + len)?).to_lowercase());
+        i += len;
+    }
+    let qtype = u16::from_be_bytes([*pkt.get(i)?, *pkt.get(i + 1)?]);
+    let qclass = u16::from_be_bytes([*pkt.get(i + 2)?, *pkt.get(i + 3)?]);
+    Some(Question { name: labels.join("."), qtype, qclass, end: i + 4 })
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    for l in name.split('.').filter(|l| !l.is_empty()) { out.push(l.len() as u8); out.extend_from_slice(l.as_bytes()); }
+    out.push(0);
+}
+
+// every record we send is about the question name, so it can point back at offset 12.
+// false if it didnt fit in MAX_UDP_LEN, the caller sets tc then
+fn put_record(out: &mut Vec<u8>, rtype: u16, ttl: u32, rdata: &[u8]) -> bool {
+    if out.len() + RECORD_OVERHEAD + rdata.len() > MAX_UDP_LEN { return false; }
+    out.extend_from_slice(&[0xc0, 0x0c]);
+    out.extend_from_slice(&rtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out.extend_from_slice(&ttl.to_be_bytes());
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(rdata);
+    true
+}
+
+fn soa_rdata(cfg: &SeederConfig) -> Vec<u8> {
+    let mut r = Vec::new();
+    put_name(&mut r, &cfg.ns);
+    put_name(&mut r, &cfg.mbox);
+    for v in [unix_now() as u32, 604_800, 86_400, 2_592_000, 604_800] { r.extend_from_slice(&v.to_be_bytes()); }
+    r
+}
+
+// required service bits for the queried name, None if it isnt ours to answer
+fn service_filter(name: &str, zone: &str) -> Option<Option<u64>> {
+    if name == zone { return Some(Some(0)); }
+    let sub = name.strip_suffix(zone)?.strip_suffix('.')?;
+    match sub.strip_prefix('x') {
+        Some(hex) if !sub.contains('.') => Some(u64::from_str_radix(hex, 16).ok()),
+        _ => Some(None),
+    }
+}
+
+// builds the reply for one query, good is the (ip, services) list to pick answers from
+pub fn answer(query: &[u8], cfg: &SeederConfig, good: &[(IpAddr, u64)]) -> Option<Vec<u8>> {
+    if query.len() < 12 || query[2] & 0x80 != 0 { return None; }
+    let opcode = (query[2] >> 3) & 0x0f;
+    let qdcount = u16::from_be_bytes([query[4], query[5]]);
+
+    let mut out = Vec::with_capacity(512);
+    out.extend_from_slice(&query[..2]);
+    // qr, copied opcode and rd, aa is set below
+    out.push(0x80 | (query[2] & 0x79));
+    out.push(0);
+    out.extend_from_slice(&[0; 8]);
+
+    let reply = |mut out: Vec<u8>, rcode: u8| { out[3] = rcode; Some(out) };
+    if opcode != 0 { return reply(out, RCODE_NOTIMP); }
+    let q = match parse_question(query) { Some(q) if qdcount == 1 => q, _ => return reply(out, RCODE_FORMERR) };
+    out[5] = 1;
+    out.extend_from_slice(&query[12..q.end]);
+
+    let zone = cfg.zone.trim_end_matches('.').to_lowercase();
+    let filter = match service_filter(&q.name, &zone) { Some(f) => f, None => return reply(out, RCODE_REFUSED) };
+    out[2] |= 0x04;
+    if q.qclass != CLASS_IN && q.qclass != CLASS_ANY { return reply(out, RCODE_NOERROR); }
+
+    let mut answers = 0u16;
+    let mut authority = 0u16;
+    let mut truncated = false;
+    let mut put = |out: &mut Vec<u8>, count: &mut u16, rtype: u16, ttl: u32, rdata: &[u8]| {
+        if put_record(out, rtype, ttl, rdata) { *count += 1; } else { truncated = true; }
+    };
+    match filter {
+        None => put(&mut out, &mut authority, TYPE_SOA, NS_TTL, &soa_rdata(cfg)),
+        Some(bits) => {
+            let want_v4 = q.qtype == TYPE_A || q.qtype == TYPE_ANY;
+            let want_v6 = q.qtype == TYPE_AAAA || q.qtype == TYPE_ANY;
+            // ns and soa first so an ANY at the apex keeps them when addresses get cut
+            if q.name == zone && (q.qtype == TYPE_NS || q.qtype == TYPE_ANY) {
+                let mut r = Vec::new(); put_name(&mut r, &cfg.ns);
+                put(&mut out, &mut answers, TYPE_NS, NS_TTL, &r);
+            }
+            if q.name == zone && (q.qtype == TYPE_SOA || q.qtype == TYPE_ANY) { put(&mut out, &mut answers, TYPE_SOA, NS_TTL, &soa_rdata(cfg)); }
+            let mut rng = rand::thread_rng();
+            let matching = |v4: bool, rng: &mut rand::rngs::ThreadRng| { let mut ips: Vec<_> = good.iter().filter(|(ip, s)| ip.is_ipv4() == v4 && s & bits == bits).map(|(ip, _)| *ip).collect(); ips.shuffle(rng); ips };
+            if want_v4 { for ip in matching(true, &mut rng).into_iter().take(MAX_A_ANSWERS) { if let IpAddr::V4(v4) = ip { put(&mut out, &mut answers, TYPE_A, ADDR_TTL, &v4.octets()); } } }
+            if want_v6 {
+                let max = if want_v4 { MAX_AAAA_ANSWERS / 2 } else { MAX_AAAA_ANSWERS };
+                for ip in matching(false, &mut rng).into_iter().take(max) { if let IpAddr::V6(v6) = ip { put(&mut out, &mut answers, TYPE_AAAA, ADDR_TTL, &v6.octets()); } }
+            }
+            // nodata still needs the soa so resolvers can cache the negative answer
+            if answers == 0 { put(&mut out, &mut authority, TYPE_SOA, NS_TTL, &soa_rdata(cfg)); }
+        }
+    }
+    if truncated { out[2] |= 0x02; }
+    out[6..8].copy_from_slice(&answers.to_be_bytes());
+    out[8..10].copy_from_slice(&authority.to_be_bytes());
+    reply(out, if filter.is_none() { RCODE_NXDOMAIN } else { RCODE_NOERROR })
+}
+
+pub struct DnsSeeder {
+    pub cfg: SeederConfig,
+    pub nodes: Arc<Mutex<HashMap<SocketAddr, KnownNode>>>,
+    // the classifier is taken from here on every query so reloads apply
+    pub settings: SharedSettings,
+}
+
+impl DnsSeeder {
+    // only nodes on the default port, a dns answer cant carry a port
+    fn good_nodes(&self) -> Vec<(IpAddr, u64)> {
+        let cl = Arc::clone(&self.settings.get().classifier);
+        let nodes = self.nodes.lock();
+        let tip = cl.tip(&nodes);
+        let port = cl.network().default_port();
+        nodes.iter().filter(|(a, n)| a.port() == port && cl.is_good_node(a, n, tip, Some(MIN_UPTIME)))
+            .map(|(a, n)| (a.ip(), n.services.map_or(0, |s| s.bits()))).collect()
+    }
+
+    pub async fn run(self, addr: SocketAddr) {
+        let sock = match UdpSocket::bind(addr).await {
+            Ok(s) => s,
+            Err(e) => { error!("cant bind dns seeder {}: {}", addr, e); return; }
+        };
+        info!("dns seeder for {} at {}", self.cfg.zone, addr);
+        let mut good = Vec::new();
+        let mut refreshed: Option<Instant> = None;
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, peer) = match sock.recv_from(&mut buf).await { Ok(x) => x, Err(e) => { debug!("dns recv failed: {}", e); continue; } };
+            if refreshed.map_or(true, |t| t.elapsed() >= CACHE_REFRESH) { good = self.good_nodes(); refreshed = Some(Instant::now()); }
+            if let Some(resp) = answer(&buf[..len], &self.cfg, &good) {
+                if let Err(e) = sock.send_to(&resp, peer).await { debug!("dns reply to {} failed: {}", peer, e); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> SeederConfig { SeederConfig { zone: "seed.example.com".into(), ns: "ns.example.com".into(), mbox: "hostmaster.example.com".into() } }
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        put_name(&mut q, name);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&CLASS_IN.to_be_bytes());
+        q
+    }
+
+    fn counts(r: &[u8]) -> (u8, u16, u16) { (r[3] & 0x0f, u16::from_be_bytes([r[6], r[7]]), u16::from_be_bytes([r[8], r[9]])) }
+
+    #[test]
+    fn answers_filtered_by_services() {
+        let good: Vec<(IpAddr, u64)> = (0..40u8).map(|i| (IpAddr::from([10, 0, 0, i]), if i < 5 { 5 } else { 1 })).collect();
+        let r = answer(&query("seed.example.com", TYPE_A), &cfg(), &good).unwrap();
+        assert_eq!(&r[..2], &[0x12, 0x34]);
+        assert_eq!(counts(&r), (RCODE_NOERROR, MAX_A_ANSWERS as u16, 0));
+        assert!(r.len() <= 512);
+
+        let r = answer(&query("x4.Seed.Example.com", TYPE_A), &cfg(), &good).unwrap();
+        assert_eq!(counts(&r), (RCODE_NOERROR, 5, 0));
+    }
+
+    #[test]
+    fn refuses_and_nxdomains() {
+        let r = answer(&query("other.org", TYPE_A), &cfg(), &[]).unwrap();
+        assert_eq!(counts(&r).0, RCODE_REFUSED);
+        let r = answer(&query("foo.seed.example.com", TYPE_A), &cfg(), &[]).unwrap();
+        assert_eq!(counts(&r), (RCODE_NXDOMAIN, 0, 1));
+        let r = answer(&query("seed.example.com", TYPE_NS), &cfg(), &[]).unwrap();
+        assert_eq!(counts(&r), (RCODE_NOERROR, 1, 0));
+    }
+
+    #[test]
+    fn any_at_the_apex_fits_in_512_bytes() {
+        let mut good: Vec<(IpAddr, u64)> = (0..40u8).map(|i| (IpAddr::from([10, 0, 0, i]), 1)).collect();
+        good.extend((0..20u16).map(|i| (IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, i]), 1)));
+        let r = answer(&query("seed.example.com", TYPE_ANY), &cfg(), &good).unwrap();
+        assert!(r.len() <= MAX_UDP_LEN);
+        assert_ne!(r[2] & 0x02, 0);
+        // ns and soa go first, then a records until not even one more fits
+        let q_end = query("seed.example.com", TYPE_ANY).len();
+        assert_eq!(u16::from_be_bytes([r[q_end + 2], r[q_end + 3]]), TYPE_NS);
+        let (rcode, answers, _) = counts(&r);
+        assert_eq!(rcode, RCODE_NOERROR);
+        assert!(answers > 2 && answers < 2 + MAX_A_ANSWERS as u16);
+        assert!(r.len() + RECORD_OVERHEAD + 4 > MAX_UDP_LEN);
+
+        let r = answer(&query("seed.example.com", TYPE_A), &cfg(), &good).unwrap();
+        assert_eq!(r[2] & 0x02, 0);
+    }
+}
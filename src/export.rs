@@ -0,0 +1,297 @@
+// export - the peer graph as graphml, gexf or graphviz dot for gephi / networkx, and the node list
+// as csv, ndjson or a zcashd contrib/seeds style nodes_main.txt
+
+use std::{collections::{HashMap, HashSet}, fmt::Write, fs, io, net::SocketAddr, path::Path, str::FromStr};
+use serde::Serialize;
+use ziggurat_core_crawler::connection::KnownConnection;
+use ziggurat_zcash::protocol::network::Network;
+use crate::{classifier::NodeClassifier, geo::GeoDb, network::{KnownNode, UptimeScores, UptimeWindow}};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat { GraphMl, Gexf, Dot }
+
+impl FromStr for GraphFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "graphml" => Ok(Self::GraphMl),
+            "gexf" => Ok(Self::Gexf),
+            "dot" | "gv" => Ok(Self::Dot),
+            _ => Err(format!("unknown graph format {:?}, expected graphml, gexf or dot", s)),
+        }
+    }
+}
+
+struct GraphNode { addr: SocketAddr, client: String, relevant: bool, height: Option<i32>, asn: Option<u32>, last_seen_secs: Option<u64> }
+struct GraphEdge { a: SocketAddr, b: SocketAddr, last_seen_secs: u64 }
+
+fn collect(nodes: &HashMap<SocketAddr, KnownNode>, conns: &HashSet<KnownConnection>, cl: &NodeClassifier, geo: &GeoDb, good_only: bool) -> (Vec<GraphNode>, Vec<GraphEdge>) {
+    let tip = cl.tip(nodes);
+    let mut out: Vec<GraphNode> = nodes.iter().filter_map(|(a, n)| {
+        let c = cl.classify(a, n, tip, None);
+        if good_only && !c.is_relevant() { return None; }
+        Some(GraphNode {
+            addr: *a, relevant: c.is_relevant(), client: c.client, height: n.start_height,
+            asn: geo.lookup(a.ip()).asn, last_seen_secs: n.last_connected.map(|t| t.elapsed().as_secs()),
+        })
+    }).collect();
+    out.sort_by_key(|n| n.addr);
+    let keep: HashSet<SocketAddr> = out.iter().map(|n| n.addr).collect();
+    let mut edges: Vec<_> = conns.iter().filter(|c| keep.contains(&c.a) && keep.contains(&c.b) && c.a != c.b)
+        .map(|c| GraphEdge { a: c.a, b: c.b, last_seen_secs: c.last_seen.elapsed().as_secs() }).collect();
+    edges.sort_by_key(|e| (e.a, e.b));
+    (out, edges)
+}
+
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn graphml(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    for (id, target, ty) in [("client", "node", "string"), ("relevant", "node", "boolean"), ("height", "node", "int"), ("asn", "node", "long"), ("last_seen_secs", "node", "long"), ("edge_last_seen_secs", "edge", "long")] {
+        let name = id.trim_start_matches("edge_");
+        let _ = writeln!(s, "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>", id, target, name, ty);
+    }
+    s.push_str("  <graph id=\"znodes\" edgedefault=\"undirected\">\n");
+    for n in nodes {
+        let _ = write!(s, "    <node id=\"{}\"><data key=\"client\">{}</data><data key=\"relevant\">{}</data>", n.addr, esc(&n.client), n.relevant);
+        if let Some(h) = n.height { let _ = write!(s, "<data key=\"height\">{}</data>", h); }
+        if let Some(a) = n.asn { let _ = write!(s, "<data key=\"asn\">{}</data>", a); }
+        if let Some(t) = n.last_seen_secs { let _ = write!(s, "<data key=\"last_seen_secs\">{}</data>", t); }
+        s.push_str("</node>\n");
+    }
+    for e in edges {
+        let _ = writeln!(s, "    <edge source=\"{}\" target=\"{}\"><data key=\"edge_last_seen_secs\">{}</data></edge>", e.a, e.b, e.last_seen_secs);
+    }
+    s.push_str("  </graph>\n</graphml>\n");
+    s
+}
+
+fn gexf(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n  <graph defaultedgetype=\"undirected\">\n");
+    s.push_str("    <attributes class=\"node\">\n");
+    for (i, (title, ty)) in [("client", "string"), ("relevant", "boolean"), ("height", "integer"), ("asn", "long"), ("last_seen_secs", "long")].iter().enumerate() {
+        let _ = writeln!(s, "      <attribute id=\"{}\" title=\"{}\" type=\"{}\"/>", i, title, ty);
+    }
+    s.push_str("    </attributes>\n    <attributes class=\"edge\">\n      <attribute id=\"0\" title=\"last_seen_secs\" type=\"long\"/>\n    </attributes>\n    <nodes>\n");
+    for n in nodes {
+        let _ = write!(s, "      <node id=\"{0}\" label=\"{0}\"><attvalues><attvalue for=\"0\" value=\"{1}\"/><attvalue for=\"1\" value=\"{2}\"/>", n.addr, esc(&n.client), n.relevant);
+        if let Some(h) = n.height { let _ = write!(s, "<attvalue for=\"2\" value=\"{}\"/>", h); }
+        if let Some(a) = n.asn { let _ = write!(s, "<attvalue for=\"3\" value=\"{}\"/>", a); }
+        if let Some(t) = n.last_seen_secs { let _ = write!(s, "<attvalue for=\"4\" value=\"{}\"/>", t); }
+        s.push_str("</attvalues></node>\n");
+    }
+    s.push_str("    </nodes>\n    <edges>\n");
+    for (i, e) in edges.iter().enumerate() {
+        let _ = writeln!(s, "      <edge id=\"{}\" source=\"{}\" target=\"{}\"><attvalues><attvalue for=\"0\" value=\"{}\"/></attvalues></edge>", i, e.a, e.b, e.last_seen_secs);
+    }
+    s.push_str("    </edges>\n  </graph>\n</gexf>\n");
+    s
+}
+
+fn dot(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let q = |x: &str| x.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut s = String::from("graph znodes {\n");
+    for n in nodes {
+        let _ = write!(s, "  \"{}\" [client=\"{}\", relevant={}", n.addr, q(&n.client), n.relevant);
+        if let Some(h) = n.height { let _ = write!(s, ", height={}", h); }
+        if let Some(a) = n.asn { let _ = write!(s, ", asn={}", a); }
+        if let Some(t) = n.last_seen_secs { let _ = write!(s, ", last_seen_secs={}", t); }
+        s.push_str("];\n");
+    }
+    for e in edges { let _ = writeln!(s, "  \"{}\" -- \"{}\" [last_seen_secs={}];", e.a, e.b, e.last_seen_secs); }
+    s.push_str("}\n");
+    s
+}
+
+// good_only keeps the classifier-relevant nodes and the edges between them
+pub fn export_graph(nodes: &HashMap<SocketAddr, KnownNode>, conns: &HashSet<KnownConnection>, cl: &NodeClassifier, geo: &GeoDb, good_only: bool, format: GraphFormat) -> String {
+    let (n, e) = collect(nodes, conns, cl, geo, good_only);
+    match format { GraphFormat::GraphMl => graphml(&n, &e), GraphFormat::Gexf => gexf(&n, &e), GraphFormat::Dot => dot(&n, &e) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeFormat { Csv, Ndjson, Seeds }
+
+impl FromStr for NodeFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            "seeds" => Ok(Self::Seeds),
+            _ => Err(format!("unknown node format {:?}, expected csv, ndjson or seeds", s)),
+        }
+    }
+}
+
+// which nodes go into a node export, shared by the export-nodes command and --export-dir
+#[derive(clap::Args, Clone)]
+pub struct NodeFilter {
+    /// only nodes passing the classifier, the same check getnodes and the dns seeder use
+    #[clap(long)]
+    pub good_only: bool,
+    /// minimum uptime 0..1 over --uptime-window, nodes below it arent relevant so --good-only drops them
+    #[clap(long, value_parser)]
+    pub min_uptime: Option<f64>,
+    /// 2h, 8h, 1d, 7d or 30d
+    #[clap(long, value_parser, default_value = "30d")]
+    pub uptime_window: UptimeWindow,
+    /// keep at most this many nodes per autonomous system, needs --geoip-asn-db
+    #[clap(long, value_parser)]
+    pub max_per_asn: Option<usize>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct NodeRow {
+    pub addr: SocketAddr,
+    pub client: String,
+    pub relevant: bool,
+    pub user_agent: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub height: Option<i32>,
+    pub services: Option<u64>,
+    pub uptime: UptimeScores,
+    pub last_seen_secs: Option<u64>,
+    pub asn: Option<u32>,
+    pub org: Option<String>,
+    pub country_code: Option<String>,
+}
+
+// contacted nodes passing the filter, best uptime first so the per-asn cap keeps the most reliable ones
+pub fn select_nodes(nodes: &HashMap<SocketAddr, KnownNode>, cl: &NodeClassifier, geo: &GeoDb, f: &NodeFilter) -> Vec<NodeRow> {
+    let tip = cl.tip(nodes);
+    let uptime = f.min_uptime.map(|m| (f.uptime_window, m));
+    let mut picked: Vec<(f64, NodeRow)> = nodes.iter().filter(|(_, n)| n.user_agent.is_some()).filter_map(|(a, n)| {
+        let c = cl.classify(a, n, tip, uptime);
+        if f.good_only && !c.is_relevant() { return None; }
+        let g = geo.lookup(a.ip());
+        Some((n.reachability.score(f.uptime_window), NodeRow {
+            addr: *a, relevant: c.is_relevant(), client: c.client, user_agent: n.user_agent.as_ref().map(|x| x.0.clone()),
+            protocol_version: n.protocol_version.map(|v| v.0), height: n.start_height, services: n.services.map(|s| s.bits()),
+            uptime: n.reachability.scores(), last_seen_secs: n.last_connected.map(|t| t.elapsed().as_secs()),
+            asn: g.asn, org: g.org, country_code: g.country_code,
+        }))
+    }).collect();
+    picked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.addr.cmp(&b.1.addr)));
+    cap_per_asn(picked.into_iter().map(|(_, r)| r), f.max_per_asn)
+}
+
+// keeps the first max rows of each asn, rows without one always stay
+fn cap_per_asn(rows: impl Iterator<Item = NodeRow>, max: Option<usize>) -> Vec<NodeRow> {
+    let mut per_asn: HashMap<u32, usize> = HashMap::new();
+    rows.filter(|r| match (max, r.asn) {
+        (Some(max), Some(asn)) => { let n = per_asn.entry(asn).or_default(); *n += 1; *n <= max }
+        _ => true,
+    }).collect()
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+pub fn export_nodes(rows: &[NodeRow], format: NodeFormat) -> String {
+    let opt = |x: Option<String>| x.unwrap_or_default();
+    let mut s = String::new();
+    match format {
+        NodeFormat::Csv => {
+            s.push_str("addr,client,relevant,user_agent,protocol_version,height,services,uptime_2h,uptime_8h,uptime_1d,uptime_7d,uptime_30d,last_seen_secs,asn,org,country_code\n");
+            for r in rows {
+                let u = &r.uptime;
+                let _ = writeln!(s, "{},{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.4},{:.4},{},{},{},{}", csv_field(&r.addr.to_string()), csv_field(&r.client), r.relevant,
+                    csv_field(&opt(r.user_agent.clone())), opt(r.protocol_version.map(|v| v.to_string())), opt(r.height.map(|v| v.to_string())),
+                    opt(r.services.map(|v| v.to_string())), u.h2, u.h8, u.d1, u.d7, u.d30, opt(r.last_seen_secs.map(|v| v.to_string())),
+                    opt(r.asn.map(|v| v.to_string())), csv_field(&opt(r.org.clone())), opt(r.country_code.clone()));
+            }
+        }
+        NodeFormat::Ndjson => { for r in rows { if let Ok(j) = serde_json::to_string(r) { s.push_str(&j); s.push('\n'); } } }
+        // one addr per line, what contrib/seeds/generate-seeds.py turns into chainparamsseeds.h
+        NodeFormat::Seeds => { for r in rows { let _ = writeln!(s, "{}", r.addr); } }
+    }
+    s
+}
+
+pub fn seeds_file_name(network: Network) -> &'static str {
+    match network { Network::Mainnet => "nodes_main.txt", Network::Testnet => "nodes_test.txt", Network::Regtest => "nodes_regtest.txt" }
+}
+
+// the scheduled export, every format into dir, written to a temp file first so readers never see half a file
+pub fn export_nodes_to_dir(dir: &Path, rows: &[NodeRow], network: Network) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, format) in [("nodes.csv", NodeFormat::Csv), ("nodes.ndjson", NodeFormat::Ndjson), (seeds_file_name(network), NodeFormat::Seeds)] {
+        let tmp = dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, export_nodes(rows, format))?;
+        fs::rename(&tmp, dir.join(name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Rules;
+    use ziggurat_zcash::protocol::{network::Network, payload::VarStr};
+
+    #[test]
+    fn writes_all_formats() {
+        let cl = NodeClassifier::new(Rules::default(), Network::Mainnet).unwrap();
+        let (a, b, c): (SocketAddr, SocketAddr, SocketAddr) = ("1.1.1.1:8233".parse().unwrap(), "2.2.2.2:8233".parse().unwrap(), "3.3.3.3:8233".parse().unwrap());
+        let mut nodes = HashMap::new();
+        nodes.insert(a, KnownNode { user_agent: Some(VarStr("/MagicBean:5.10.0/".into())), start_height: Some(2_700_000), ..Default::default() });
+        nodes.insert(b, KnownNode { user_agent: Some(VarStr("/MagicBean:5.10.0/".into())), start_height: Some(2_700_000), ..Default::default() });
+        nodes.insert(c, KnownNode::default());
+        let conns: HashSet<_> = [KnownConnection::new(a, b), KnownConnection::new(a, c)].into_iter().collect();
+        let geo = GeoDb::default();
+
+        let g = export_graph(&nodes, &conns, &cl, &geo, true, GraphFormat::GraphMl);
+        assert_eq!(g.matches("<node ").count(), 2);
+        assert_eq!(g.matches("<edge ").count(), 1);
+        assert!(g.contains("<data key=\"client\">zcashd</data>"));
+
+        let g = export_graph(&nodes, &conns, &cl, &geo, false, GraphFormat::Gexf);
+        assert_eq!(g.matches("<node ").count(), 3);
+        assert_eq!(g.matches("<edge ").count(), 2);
+
+        let g = export_graph(&nodes, &conns, &cl, &geo, true, GraphFormat::Dot);
+        assert!(g.contains("\"1.1.1.1:8233\" -- \"2.2.2.2:8233\""));
+        assert_eq!("GEXF".parse::<GraphFormat>(), Ok(GraphFormat::Gexf));
+    }
+
+    #[test]
+    fn node_exports_and_asn_cap() {
+        let cl = NodeClassifier::new(Rules::default(), Network::Mainnet).unwrap();
+        let mut nodes = HashMap::new();
+        for (i, ua) in ["/MagicBean:5.10.0/", "/Zebra:2.1.0/", "/MagicBean:5.10.0(a, b)/"].iter().enumerate() {
+            let mut n = KnownNode { user_agent: Some(VarStr(ua.to_string())), start_height: Some(2_700_000), ..Default::default() };
+            n.reachability.update(true, 1_000);
+            nodes.insert(SocketAddr::from(([10, 0, 0, i as u8], 8233)), n);
+        }
+        nodes.insert("[2001:db8::1]:8233".parse().unwrap(), KnownNode::default());
+        let mut down = KnownNode { user_agent: Some(VarStr("/Zebra:2.1.0/".into())), start_height: Some(2_700_000), ..Default::default() };
+        down.reachability.update(false, 1_000);
+        nodes.insert("10.0.0.9:8233".parse().unwrap(), down);
+        let f = NodeFilter { good_only: true, min_uptime: Some(0.5), uptime_window: UptimeWindow::D30, max_per_asn: Some(1) };
+        // no asn db, so the cap doesnt apply
+        let rows = select_nodes(&nodes, &cl, &GeoDb::default(), &f);
+        assert_eq!(rows.len(), 3);
+        // without good_only the low uptime node stays, just not relevant
+        let all = select_nodes(&nodes, &cl, &GeoDb::default(), &NodeFilter { good_only: false, ..f.clone() });
+        assert_eq!(all.iter().filter(|r| !r.relevant).map(|r| r.addr.to_string()).collect::<Vec<_>>(), vec!["10.0.0.9:8233"]);
+
+        let csv = export_nodes(&rows, NodeFormat::Csv);
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.contains("\"/MagicBean:5.10.0(a, b)/\""));
+        assert_eq!(export_nodes(&rows, NodeFormat::Ndjson).lines().count(), 3);
+        assert_eq!(export_nodes(&rows, NodeFormat::Seeds).lines().next(), Some("10.0.0.0:8233"));
+
+        // two in one asn, one in another and one without
+        let mut rows = rows;
+        let extra = NodeRow { addr: "[2001:db8::1]:8233".parse().unwrap(), ..rows[0].clone() };
+        rows.push(extra);
+        for (r, asn) in rows.iter_mut().zip([Some(64_500), Some(64_500), Some(64_501), None]) { r.asn = asn; }
+        let capped = cap_per_asn(rows.clone().into_iter(), Some(1));
+        assert_eq!(capped.iter().map(|r| r.addr).collect::<Vec<_>>(), vec![rows[0].addr, rows[2].addr, rows[3].addr]);
+        assert_eq!(cap_per_asn(rows.into_iter(), None).len(), 4);
+    }
+}
@@ -0,0 +1,49 @@
+// geo lookup - country, city, coordinates and asn from local mmdb files (maxmind geolite2 or db-ip lite)
+
+use std::{net::IpAddr, path::Path};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Serialize;
+
+#[derive(Clone, Default, Serialize)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+    pub city: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub asn: Option<u32>,
+    pub org: Option<String>,
+}
+
+// either db is optional, lookups just leave the matching fields empty
+#[derive(Default)]
+pub struct GeoDb {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+fn english(names: &Option<std::collections::BTreeMap<&str, &str>>) -> Option<String> {
+    names.as_ref().and_then(|n| n.get("en")).map(|s| s.to_string())
+}
+
+impl GeoDb {
+    pub fn open<P: AsRef<Path>>(city: Option<P>, asn: Option<P>) -> Result<Self, MaxMindDBError> {
+        Ok(Self { city: city.map(Reader::open_readfile).transpose()?, asn: asn.map(Reader::open_readfile).transpose()? })
+    }
+
+    pub fn is_empty(&self) -> bool { self.city.is_none() && self.asn.is_none() }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut g = GeoInfo::default();
+        if let Some(Ok(c)) = self.city.as_ref().map(|r| r.lookup::<geoip2::City>(ip)) {
+            if let Some(ref country) = c.country { g.country = english(&country.names); g.country_code = country.iso_code.map(String::from); }
+            g.city = c.city.as_ref().and_then(|x| english(&x.names));
+            if let Some(ref loc) = c.location { g.lat = loc.latitude; g.lon = loc.longitude; }
+        }
+        if let Some(Ok(a)) = self.asn.as_ref().map(|r| r.lookup::<geoip2::Asn>(ip)) {
+            g.asn = a.autonomous_system_number;
+            g.org = a.autonomous_system_organization.map(String::from);
+        }
+        g
+    }
+}
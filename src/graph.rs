@@ -0,0 +1,179 @@
+// graph analytics - centrality, clustering, diameter and components of the good-node subgraph
+// edges are addr gossip (a told us about b) treated as undirected, recomputed every summary
+
+use std::{collections::{BTreeMap, HashMap, VecDeque}, net::SocketAddr};
+use serde::Serialize;
+use spectre::{edge::Edge, graph::Graph};
+
+// nodes reported in central_nodes
+const TOP_CENTRAL: usize = 20;
+// betweenness this many standard deviations above the mean marks a node as over-central
+const OVER_CENTRAL_SIGMA: f64 = 3.0;
+// a second component at least this share of the graph is reported as a possible partition
+const PARTITION_SHARE: f64 = 0.05;
+
+#[derive(Clone, Default, Serialize)]
+pub struct DegreeStats {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: usize,
+    // degree -> number of nodes
+    pub distribution: BTreeMap<usize, usize>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CentralNode {
+    pub addr: SocketAddr,
+    pub degree: usize,
+    // normalized to 0..1 by the number of node pairs
+    pub betweenness: f64,
+    // scaled so the most central node is 1
+    pub eigenvector: f64,
+    pub over_central: bool,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct GraphMetrics {
+    pub nodes: usize,
+    pub edges: usize,
+    pub density: f64,
+    pub degree: DegreeStats,
+    pub avg_clustering: f64,
+    // of the largest component
+    pub diameter: usize,
+    // component sizes, largest first
+    pub components: Vec<usize>,
+    pub partition_suspected: bool,
+    pub central_nodes: Vec<CentralNode>,
+}
+
+struct Adjacency { addrs: Vec<SocketAddr>, adj: Vec<Vec<usize>> }
+
+impl Adjacency {
+    fn new(nodes: &[SocketAddr], edges: &[(SocketAddr, SocketAddr)]) -> Self {
+        let index: HashMap<SocketAddr, usize> = nodes.iter().enumerate().map(|(i, a)| (*a, i)).collect();
+        let mut adj = vec![Vec::new(); nodes.len()];
+        for (a, b) in edges {
+            if let (Some(&i), Some(&j)) = (index.get(a), index.get(b)) { if i != j { adj[i].push(j); adj[j].push(i); } }
+        }
+        for l in adj.iter_mut() { l.sort_unstable(); l.dedup(); }
+        Self { addrs: nodes.to_vec(), adj }
+    }
+
+    fn len(&self) -> usize { self.addrs.len() }
+
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.len()];
+        let mut out = Vec::new();
+        for s in 0..self.len() {
+            if seen[s] { continue; }
+            seen[s] = true;
+            let mut comp = vec![s];
+            let mut i = 0;
+            while i < comp.len() {
+                for &v in &self.adj[comp[i]] { if !seen[v] { seen[v] = true; comp.push(v); } }
+                i += 1;
+            }
+            out.push(comp);
+        }
+        out.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        out
+    }
+
+    // longest shortest path from s, within its component
+    fn eccentricity(&self, s: usize) -> usize {
+        let mut dist = vec![usize::MAX; self.len()];
+        dist[s] = 0;
+        let mut far = 0;
+        let mut q = VecDeque::from([s]);
+        while let Some(v) = q.pop_front() {
+            far = dist[v];
+            for &w in &self.adj[v] { if dist[w] == usize::MAX { dist[w] = dist[v] + 1; q.push_back(w); } }
+        }
+        far
+    }
+
+    fn clustering(&self, v: usize) -> f64 {
+        let l = &self.adj[v];
+        if l.len() < 2 { return 0.0; }
+        let mut links = 0;
+        for (k, &a) in l.iter().enumerate() { for &b in &l[k + 1..] { if self.adj[a].binary_search(&b).is_ok() { links += 1; } } }
+        2.0 * links as f64 / (l.len() * (l.len() - 1)) as f64
+    }
+}
+
+pub fn analyze(nodes: &[SocketAddr], edges: &[(SocketAddr, SocketAddr)]) -> GraphMetrics {
+    let g = Adjacency::new(nodes, edges);
+    let n = g.len();
+    if n == 0 { return GraphMetrics::default(); }
+    let degrees: Vec<usize> = g.adj.iter().map(|l| l.len()).collect();
+    let num_edges = degrees.iter().sum::<usize>() / 2;
+
+    let mut sorted = degrees.clone();
+    sorted.sort_unstable();
+    let mut distribution = BTreeMap::new();
+    for d in &degrees { *distribution.entry(*d).or_default() += 1; }
+    let degree = DegreeStats { min: sorted[0], max: sorted[n - 1], mean: 2.0 * num_edges as f64 / n as f64, median: sorted[n / 2], distribution };
+
+    let components = g.components();
+    let diameter = components[0].iter().map(|&v| g.eccentricity(v)).max().unwrap_or(0);
+    let components: Vec<usize> = components.iter().map(|c| c.len()).collect();
+    let partition_suspected = components.get(1).map_or(false, |c| *c as f64 >= PARTITION_SHARE * n as f64 && *c > 1);
+
+    // centralities come from spectre over the good-node subgraph, nodes without edges arent in it and score 0
+    let mut graph = Graph::new();
+    for (v, l) in g.adj.iter().enumerate() { for &w in l { if v < w { graph.insert(Edge::new(g.addrs[v], g.addrs[w])); } } }
+    let threads = std::thread::available_parallelism().map_or(1, |t| t.get());
+    let between = graph.betweenness_centrality(threads, graph.vertex_count() > 2);
+    let eigen = graph.eigenvalue_centrality();
+    let max_eigen = eigen.values().cloned().fold(0.0, f64::max);
+    let betweenness: Vec<f64> = g.addrs.iter().map(|a| between.get(a).copied().unwrap_or(0.0)).collect();
+    let eigen: Vec<f64> = g.addrs.iter().map(|a| eigen.get(a).map_or(0.0, |e| if max_eigen > 0.0 { e / max_eigen } else { 0.0 })).collect();
+    let mean = betweenness.iter().sum::<f64>() / n as f64;
+    let sd = (betweenness.iter().map(|b| (b - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| betweenness[*b].total_cmp(&betweenness[*a]));
+    let central_nodes = order.into_iter().take(TOP_CENTRAL).map(|i| CentralNode {
+        addr: g.addrs[i], degree: degrees[i], betweenness: betweenness[i], eigenvector: eigen[i],
+        over_central: sd > 0.0 && betweenness[i] > mean + OVER_CENTRAL_SIGMA * sd,
+    }).collect();
+
+    GraphMetrics {
+        nodes: n, edges: num_edges,
+        density: if n > 1 { 2.0 * num_edges as f64 / (n * (n - 1)) as f64 } else { 0.0 },
+        degree,
+        avg_clustering: (0..n).map(|v| g.clustering(v)).sum::<f64>() / n as f64,
+        diameter, components, partition_suspected, central_nodes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(i: u8) -> SocketAddr { SocketAddr::from(([10, 0, 0, i], 8233)) }
+
+    #[test]
+    fn star_and_split() {
+        // a star around node 0, a triangle, a longer path and an edge to a node that isnt good
+        let nodes: Vec<_> = (0..13).map(addr).collect();
+        let mut edges: Vec<_> = (1..6).map(|i| (addr(0), addr(i))).collect();
+        edges.extend([(addr(6), addr(7)), (addr(7), addr(8)), (addr(8), addr(6))]);
+        edges.extend([(addr(9), addr(10)), (addr(10), addr(11)), (addr(11), addr(12)), (addr(12), addr(20))]);
+        let m = analyze(&nodes, &edges);
+
+        assert_eq!((m.nodes, m.edges), (13, 11));
+        assert_eq!(m.components, vec![6, 4, 3]);
+        assert!(m.partition_suspected);
+        // the path is longer but only the largest component counts
+        assert_eq!(m.diameter, 2);
+        assert_eq!(m.degree.max, 5);
+        assert_eq!(m.central_nodes[0].addr, addr(0));
+        assert!(m.central_nodes[0].betweenness > 0.0);
+        assert_eq!(m.central_nodes[0].eigenvector, 1.0);
+        // the triangle is fully clustered, the rest not at all
+        assert!((m.avg_clustering - 3.0 / 13.0).abs() < 1e-9);
+    }
+}
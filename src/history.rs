@@ -0,0 +1,140 @@
+// stats history - bounded, downsampled time series of network snapshots for charting
+
+use std::{collections::{BTreeMap, HashMap, VecDeque}, fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Write}, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::rpc::Stats;
+
+// (max age, resolution) in secs - full resolution for a day, then 15 min for a week, then hourly for 90 days
+const TIERS: [(u64, u64); 3] = [(86_400, 0), (7 * 86_400, 900), (90 * 86_400, 3600)];
+// rewrite the file after this many appends so it doesnt grow past what we keep in memory
+const COMPACT_EVERY: usize = 60;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryPoint {
+    pub timestamp: u64,
+    pub stats: Stats,
+    pub protocol_versions: BTreeMap<u32, usize>,
+}
+
+impl HistoryPoint {
+    pub fn new(stats: Stats, versions: &HashMap<u32, usize>) -> Self {
+        Self { timestamp: now(), stats, protocol_versions: versions.iter().map(|(k, v)| (*k, *v)).collect() }
+    }
+}
+
+#[derive(Default)]
+pub struct StatsHistory {
+    points: VecDeque<HistoryPoint>,
+    path: Option<PathBuf>,
+    appended: usize,
+}
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) }
+
+impl StatsHistory {
+    // reads back whatever an earlier run left in the file, a missing file just means no history yet
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let mut h = Self { path: Some(path.clone()), ..Default::default() };
+        match File::open(&path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    match serde_json::from_str::<HistoryPoint>(&line?) {
+                        Ok(p) => h.points.push_back(p),
+                        Err(e) => warn!("skipping bad history line: {}", e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        h.points.make_contiguous().sort_by_key(|p| p.timestamp);
+        h.compact(now());
+        h.rewrite()?;
+        Ok(h)
+    }
+
+    pub fn push(&mut self, point: HistoryPoint) {
+        if let Some(ref path) = self.path {
+            let line = serde_json::to_string(&point).map_err(io::Error::from);
+            let res = line.and_then(|l| OpenOptions::new().create(true).append(true).open(path).and_then(|mut f| writeln!(f, "{}", l)));
+            if let Err(e) = res { warn!("cant append to history file: {}", e); }
+        }
+        self.points.push_back(point);
+        self.appended += 1;
+        if self.appended >= COMPACT_EVERY {
+            self.appended = 0;
+            self.compact(now());
+            if let Err(e) = self.rewrite() { warn!("cant rewrite history file: {}", e); }
+        }
+    }
+
+    // keeps the newest point of every bucket of its tier, drops anything older than the last tier
+    fn compact(&mut self, now: u64) {
+        let mut seen = std::collections::HashSet::new();
+        let mut kept = VecDeque::with_capacity(self.points.len());
+        for p in self.points.drain(..).rev() {
+            let age = now.saturating_sub(p.timestamp);
+            let tier = match TIERS.iter().position(|(max_age, _)| age <= *max_age) { Some(t) => t, None => continue };
+            let res = TIERS[tier].1;
+            if res == 0 || seen.insert((tier, p.timestamp / res)) { kept.push_front(p); }
+        }
+        self.points = kept;
+    }
+
+    fn rewrite(&self) -> io::Result<()> {
+        let path = match self.path { Some(ref p) => p, None => return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = io::BufWriter::new(File::create(&tmp)?);
+            for p in &self.points { writeln!(f, "{}", serde_json::to_string(p)?)?; }
+            f.flush()?;
+        }
+        fs::rename(tmp, path)
+    }
+
+    // points in [from, to], at most one (the newest) per `resolution` secs, 0 means everything
+    pub fn query(&self, from: u64, to: u64, resolution: u64) -> Vec<HistoryPoint> {
+        let mut out: Vec<HistoryPoint> = Vec::new();
+        for p in self.points.iter().filter(|p| p.timestamp >= from && p.timestamp <= to) {
+            match out.last_mut() {
+                Some(last) if resolution > 0 && last.timestamp / resolution == p.timestamp / resolution => *last = p.clone(),
+                _ => out.push(p.clone()),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: u64) -> HistoryPoint {
+        let stats = Stats { num_known_nodes: 0, num_contacted_nodes: 0, num_relevant_zcash_nodes: 0, num_zcashd_nodes: 0, num_zebra_nodes: 0,
+            num_flux_nodes: 0, num_other_nodes: 0, tip_height_estimate: 0, crawler_runtime_secs: 0, num_unique_contacted_nodes: 0, num_unique_relevant_nodes: 0 };
+        HistoryPoint { timestamp, stats, protocol_versions: BTreeMap::new() }
+    }
+
+    #[test]
+    fn compact_downsamples_by_age() {
+        let now = 100 * 86_400;
+        let mut h = StatsHistory::default();
+        // a minute apart: the last day stays at full resolution, the day before collapses into 15 min buckets, too old gets dropped
+        for t in (now - 2 * 86_400..=now).step_by(60) { h.points.push_back(point(t)); }
+        h.points.push_front(point(1));
+        h.compact(now);
+
+        assert!(h.points.iter().all(|p| p.timestamp != 1));
+        assert_eq!(h.points.iter().filter(|p| now - p.timestamp <= 86_400).count(), 1441);
+        assert_eq!(h.points.iter().filter(|p| now - p.timestamp > 86_400).count(), 96);
+    }
+
+    #[test]
+    fn query_keeps_newest_per_bucket() {
+        let mut h = StatsHistory::default();
+        for t in [10, 20, 70, 130, 140] { h.points.push_back(point(t)); }
+        let ts: Vec<_> = h.query(0, 135, 60).iter().map(|p| p.timestamp).collect();
+        assert_eq!(ts, vec![20, 70, 130]);
+    }
+}
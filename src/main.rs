@@ -3,53 +3,190 @@
 
 use std::{
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
     sync::Arc,
     thread,
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dns_lookup::lookup_host;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use pea2pea::{
     protocols::{Handshake, Reading, Writing},
     Pea2Pea,
 };
-use rand::seq::SliceRandom;
 use tokio::{signal, time::sleep};
-use tracing::{error, info};
+use tracing::{debug, error, info};
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use ziggurat_core_crawler::summary::NetworkSummary;
+use ziggurat_zcash::protocol::network::Network;
 
 use crate::{
-    metrics::{NetworkMetrics, ZCASH_P2P_DEFAULT_MAINNET_PORT},
-    network::{ConnectionState, KnownNode},
-    protocol::{Crawler, MAX_WAIT_FOR_ADDR_SECS, NUM_CONN_ATTEMPTS_PERIODIC, RECONNECT_INTERVAL_SECS},
-    rpc::{initialize_rpc_server, RpcContext},
+    chain::{Checkpoint, HeaderChain, TIP_PEERS},
+    classifier::NodeClassifier,
+    config::{ConfigOverrides, SettingsSource, SharedSettings},
+    dns::{DnsSeeder, SeederConfig},
+    export::{export_graph, export_nodes, export_nodes_to_dir, select_nodes, GraphFormat, NodeFilter, NodeFormat},
+    geo::GeoDb,
+    graph::GraphMetrics,
+    history::{HistoryPoint, StatsHistory},
+    mempool::{MempoolTracker, OBSERVER_PEERS},
+    metrics::NetworkMetrics,
+    network::{ConnectionState, KnownNetwork},
+    prometheus::{serve_metrics, MetricsExporter},
+    protocol::Crawler,
+    readiness::{Readiness, DEFAULT_UPGRADES},
+    rpc::{compute_stats, initialize_rpc_server, RpcContext},
+    scheduler::Scheduler,
+    store::NodeStore,
 };
 
+mod aliases;
+mod chain;
+mod classifier;
+mod config;
+mod dns;
+mod export;
+mod geo;
+mod graph;
+mod history;
+mod mempool;
 mod metrics;
 mod network;
+mod prometheus;
+mod propagation;
 mod protocol;
+mod readiness;
 mod rpc;
+mod scheduler;
+mod store;
+mod user_agent;
 
 const SEED_WAIT_INTERVAL: u64 = 500;
 const SEED_TIMEOUT: u64 = 120_000;
-const DNS_REFRESH: u64 = 120;
-const SUMMARY_INTERVAL: u64 = 60;
 const LOG_FILE: &str = "crawler-log.txt";
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    #[clap(short, long, value_parser, num_args(1..), required = true)]
-    seed_addrs: Vec<String>,
-    #[clap(short, long, value_parser, default_value_t = 10)]
-    crawl_interval: u64,
+    #[clap(subcommand)]
+    command: Option<Command>,
     #[clap(short, long, value_parser)]
     rpc_addr: Option<SocketAddr>,
-    #[clap(short, long, value_parser, default_value_t = ZCASH_P2P_DEFAULT_MAINNET_PORT)]
-    node_listening_port: u16,
+    /// serves prometheus metrics on http://<addr>/metrics
+    #[clap(long, value_parser)]
+    metrics_addr: Option<SocketAddr>,
+    /// defaults to the standard port of the selected network
+    #[clap(short, long, value_parser)]
+    node_listening_port: Option<u16>,
+    /// mainnet, testnet or regtest
+    #[clap(long, value_parser, default_value_t = Network::Mainnet)]
+    network: Network,
+    /// directory of the on-disk node database, state is kept in memory only if not set
+    #[clap(long, value_parser)]
+    db_path: Option<PathBuf>,
+    /// json lines file the stats history is kept in, in memory only if not set
+    #[clap(long, value_parser)]
+    history_file: Option<PathBuf>,
+    /// runs a dns seeder on this udp address, needs --dns-zone
+    #[clap(long, value_parser, requires = "dns_zone")]
+    dns_addr: Option<SocketAddr>,
+    /// zone the seeder is authoritative for, e.g. seed.example.com
+    #[clap(long, value_parser)]
+    dns_zone: Option<String>,
+    /// hostname of the seeder itself, used for the NS and SOA records
+    #[clap(long, value_parser, default_value = "localhost")]
+    dns_ns: String,
+    /// SOA contact mailbox, with the @ written as a dot
+    #[clap(long, value_parser, default_value = "hostmaster.localhost")]
+    dns_mbox: String,
+    /// GeoLite2-City or dbip-city-lite mmdb used to geolocate nodes
+    #[clap(long, value_parser)]
+    geoip_city_db: Option<PathBuf>,
+    /// GeoLite2-ASN or dbip-asn-lite mmdb used for asn and organization
+    #[clap(long, value_parser)]
+    geoip_asn_db: Option<PathBuf>,
+    /// toml file with the node classification rules, the bundled classifier.toml is used if not set
+    #[clap(long, value_parser)]
+    classifier_rules: Option<PathBuf>,
+    /// <height>:<hash> to start following headers from, genesis if not set
+    #[clap(long, value_parser)]
+    checkpoint: Option<Checkpoint>,
+    /// start following headers from a recent block bundled with the binary instead of genesis
+    #[clap(long, conflicts_with = "checkpoint")]
+    recent_checkpoint: bool,
+    /// connections kept open to follow the chain and time block propagation, more give finer curves
+    #[clap(long, value_parser, default_value_t = TIP_PEERS)]
+    tip_peers: usize,
+    /// records tx announcements from all peers and keeps tip peers and observers connected to ask for their mempool
+    #[clap(long)]
+    observe_mempool: bool,
+    /// peers kept connected for relay stats besides the tip peers, with --observe-mempool
+    #[clap(long, value_parser, default_value_t = OBSERVER_PEERS)]
+    observer_peers: usize,
+    /// share of newly announced txs fetched to classify by version, with --observe-mempool
+    #[clap(long, value_parser, default_value_t = 0.1)]
+    mempool_sample_rate: f64,
+    /// toml file with the network upgrades to report readiness for, the bundled upgrades.toml if not set
+    #[clap(long, value_parser)]
+    upgrades: Option<PathBuf>,
+    /// writes nodes.csv, nodes.ndjson and a nodes_main.txt seed file here after every summary
+    #[clap(long, value_parser)]
+    export_dir: Option<PathBuf>,
+    #[clap(flatten)]
+    export_filter: NodeFilter,
+    /// toml file with the crawler settings, see znodes.toml, flags below override it. reread on SIGHUP
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+    #[clap(flatten)]
+    overrides: ConfigOverrides,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// writes the peer graph stored in --db-path as graphml, gexf or dot, the crawler must not be running
+    ExportGraph {
+        /// graphml, gexf or dot
+        #[clap(long, value_parser, default_value = "graphml")]
+        format: GraphFormat,
+        /// every known node instead of only the relevant ones
+        #[clap(long)]
+        all: bool,
+        /// stdout if not set
+        #[clap(short, long, value_parser)]
+        out: Option<PathBuf>,
+    },
+    /// writes the nodes stored in --db-path as csv, ndjson or a seed file, the crawler must not be running
+    ExportNodes {
+        /// csv, ndjson or seeds
+        #[clap(long, value_parser, default_value = "csv")]
+        format: NodeFormat,
+        #[clap(flatten)]
+        filter: NodeFilter,
+        /// stdout if not set
+        #[clap(short, long, value_parser)]
+        out: Option<PathBuf>,
+    },
+}
+
+fn write_out(out: Option<&PathBuf>, data: &str) -> Result<(), String> {
+    match out {
+        Some(p) => std::fs::write(p, data).map_err(|e| format!("cant write {}: {}", p.display(), e)),
+        None => { print!("{}", data); Ok(()) }
+    }
+}
+
+fn run_command(cmd: &Command, args: &Args, cl: &NodeClassifier) -> Result<(), String> {
+    let db = args.db_path.as_ref().ok_or("needs --db-path")?;
+    let store = NodeStore::open(db).map_err(|e| format!("cant open node db: {}", e))?;
+    let net = KnownNetwork::default();
+    store.load_with(&net, None).map_err(|e| format!("cant load node db: {}", e))?;
+    let geo = GeoDb::open(args.geoip_city_db.as_ref(), args.geoip_asn_db.as_ref()).map_err(|e| format!("cant open geoip db: {}", e))?;
+    match cmd {
+        Command::ExportGraph { format, all, out } => write_out(out.as_ref(), &export_graph(&net.nodes(), &net.connections(), cl, &geo, !all, *format)),
+        Command::ExportNodes { format, filter, out } => write_out(out.as_ref(), &export_nodes(&select_nodes(&net.nodes(), cl, &geo, filter), *format)),
+    }
 }
 
 fn setup_logging(level: LevelFilter) {
@@ -90,45 +227,118 @@ fn parse_addrs(addrs: Vec<String>, default_port: u16) -> Vec<SocketAddr> {
 async fn main() {
     setup_logging(LevelFilter::INFO);
     let args = Args::parse();
-    let seeds = args.seed_addrs.clone();
-    let addrs = parse_addrs(seeds.clone(), args.node_listening_port);
-    if addrs.is_empty() { error!("no valid seeds"); return; }
+    let network = args.network;
+    let chain = Arc::new(RwLock::new(HeaderChain::new(args.checkpoint.unwrap_or_else(|| if args.recent_checkpoint { Checkpoint::recent(network) } else { Checkpoint::genesis(network) }), network)));
+    let source = SettingsSource { config: args.config.clone(), overrides: args.overrides.clone(), rules: args.classifier_rules.clone(), network, chain: Arc::clone(&chain) };
+    let settings = match SharedSettings::open(source) { Ok(s) => s, Err(e) => { error!("{}", e); return; } };
+
+    // offline commands work on the stored state, no crawling
+    if let Some(ref cmd) = args.command {
+        if let Err(e) = run_command(cmd, &args, &settings.get().classifier) { error!("{}", e); }
+        return;
+    }
+
+    let port = args.node_listening_port.unwrap_or_else(|| network.default_port());
+    let addrs = parse_addrs(settings.get().config.seeds.clone(), port);
+    if addrs.is_empty() { error!("no valid seeds, set them with --seed-addrs or seeds in --config"); return; }
+    let readiness = match args.upgrades.as_ref() {
+        Some(p) => Readiness::load(p, network),
+        None => Readiness::parse(DEFAULT_UPGRADES, network),
+    };
+    let readiness = match readiness { Ok(r) => Arc::new(r), Err(e) => { error!("bad upgrade list: {}", e); return; } };
+    info!("tracking readiness for upgrades {:?}", readiness.names());
 
-    let crawler = Crawler::new().await;
+    let mempool = args.observe_mempool.then(|| Arc::new(Mutex::new(MempoolTracker::new(args.mempool_sample_rate))));
+    let mut crawler = Crawler::new(network, Arc::clone(&chain), args.tip_peers, settings.clone()).await;
+    if let Some(ref m) = mempool { crawler = crawler.with_mempool(Arc::clone(m), args.observer_peers); }
+    let store = match args.db_path.as_ref().map(NodeStore::open) {
+        Some(Ok(s)) => Some(s),
+        Some(Err(e)) => { error!("cant open node db: {}", e); return; }
+        None => None,
+    };
+    if let Some(ref s) = store {
+        match s.load_with(&crawler.known_network, Some(settings.get().config.last_seen_cutoff_secs)) {
+            Ok((n, c)) => info!("loaded {} nodes and {} connections from db", n, c),
+            Err(e) => error!("cant load node db: {}", e),
+        }
+    }
     let mut metrics = NetworkMetrics::default();
     let summary = Arc::new(Mutex::new(NetworkSummary::default()));
     let nodes_snap = Arc::new(Mutex::new(std::collections::HashMap::new()));
+    let conns_snap = Arc::new(Mutex::new(std::collections::HashSet::new()));
+    let history = match args.history_file.clone().map(StatsHistory::open) {
+        Some(Ok(h)) => h,
+        Some(Err(e)) => { error!("cant open history file: {}", e); return; }
+        None => StatsHistory::default(),
+    };
+    let history = Arc::new(Mutex::new(history));
+    let graph_metrics = Arc::new(Mutex::new(GraphMetrics::default()));
+    let geo = match GeoDb::open(args.geoip_city_db.as_ref(), args.geoip_asn_db.as_ref()) {
+        Ok(g) => { if !g.is_empty() { info!("geoip enrichment enabled"); } Arc::new(g) }
+        Err(e) => { error!("cant open geoip db: {}", e); return; }
+    };
 
     let _rpc = if let Some(addr) = args.rpc_addr {
-        Some(initialize_rpc_server(addr, RpcContext::new(Arc::clone(&summary), Arc::clone(&nodes_snap))).await)
+        let ctx = RpcContext {
+            summary: Arc::clone(&summary), nodes: Arc::clone(&nodes_snap), conns: Arc::clone(&conns_snap), history: Arc::clone(&history), settings: settings.clone(),
+            geo: Arc::clone(&geo), chain: Arc::clone(&chain), propagation: Arc::clone(&crawler.propagation), mempool: mempool.clone(), readiness: Arc::clone(&readiness),
+            graph: Arc::clone(&graph_metrics),
+        };
+        Some(initialize_rpc_server(addr, ctx).await)
     } else { None };
 
+    if let Some(addr) = args.metrics_addr {
+        let ex = MetricsExporter { counters: Arc::clone(&crawler.counters), nodes: Arc::clone(&nodes_snap), settings: settings.clone(), start_time: crawler.start_time };
+        tokio::spawn(serve_metrics(addr, ex));
+    }
+
+    if let (Some(addr), Some(zone)) = (args.dns_addr, args.dns_zone.clone()) {
+        let cfg = SeederConfig { zone, ns: args.dns_ns.clone(), mbox: args.dns_mbox.clone() };
+        tokio::spawn(DnsSeeder { cfg, nodes: Arc::clone(&nodes_snap), settings: settings.clone() }.run(addr));
+    }
+
     crawler.enable_handshake().await;
     crawler.enable_reading().await;
     crawler.enable_writing().await;
 
     for addr in &addrs {
-        let c = crawler.clone();
-        let a = *addr;
-        tokio::spawn(async move { c.known_network.nodes.write().insert(a, KnownNode::default()); let _ = c.connect(a).await; });
+        crawler.known_network.add_node(*addr);
+        let (c, a) = (crawler.clone(), *addr);
+        if let Some(slot) = crawler.reserve(a) { tokio::spawn(async move { let _ = c.connect(a, slot).await; }); }
     }
 
-    // dns refresh task
-    { let c = crawler.clone(); let s = seeds.clone(); let p = args.node_listening_port;
+    // dns refresh task, the seed list is read every time so reloads add and drop seeds
+    { let c = crawler.clone(); let p = port;
     tokio::spawn(async move {
         loop {
-            sleep(Duration::from_secs(DNS_REFRESH)).await;
-            for seed in &s {
-                let resolved = parse_addrs(vec![seed.clone()], p);
+            sleep(Duration::from_secs(c.settings.get().config.dns_refresh_secs)).await;
+            let seeds = c.settings.get().config.seeds.clone();
+            for seed in seeds {
+                let resolved = parse_addrs(vec![seed], p);
                 for addr in resolved {
-                    let mut new = false;
-                    { let mut nodes = c.known_network.nodes.write(); if !nodes.contains_key(&addr) { nodes.insert(addr, KnownNode::default()); new = true; } }
-                    if new { let cc = c.clone(); tokio::spawn(async move { let _ = cc.connect(addr).await; }); }
+                    if !c.known_network.add_node(addr) { continue; }
+                    if let Some(slot) = c.reserve(addr) { let cc = c.clone(); tokio::spawn(async move { let _ = cc.connect(addr, slot).await; }); }
                 }
             }
         }
     }); }
 
+    // SIGHUP rereads --config and --classifier-rules, same as the reloadconfig rpc
+    #[cfg(unix)]
+    { let s = settings.clone();
+    tokio::spawn(async move {
+        let mut hup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(h) => h,
+            Err(e) => { error!("cant listen for SIGHUP: {}", e); return; }
+        };
+        while hup.recv().await.is_some() {
+            match s.reload() {
+                Ok(n) => info!("config reloaded, {} seeds", n.config.seeds.len()),
+                Err(e) => error!("config reload failed, keeping the old one: {}", e),
+            }
+        }
+    }); }
+
     info!("waiting for connection...");
     for _ in 0..30 { if crawler.node().num_connected() >= 1 { break; } sleep(Duration::from_millis(500)).await; }
 
@@ -139,48 +349,65 @@ async fn main() {
         sleep(Duration::from_millis(SEED_WAIT_INTERVAL)).await;
     }
 
+    let export = args.export_dir.clone().map(|d| (d, args.export_filter.clone()));
     let c = crawler.clone();
     let crawl_task = tokio::spawn(async move {
         loop {
-            info!(parent: c.node().span(), "crawling - conn:{} known:{}", c.node().num_connected(), c.known_network.num_nodes());
+            info!(parent: c.node().span(), "crawling - conn:{} known:{} overlay:{}", c.node().num_connected(), c.known_network.num_nodes(), c.known_network.num_overlay_addrs());
 
-            for (addr, _) in c.known_network.nodes().into_iter().filter(|(_, n)| {
-                n.state == ConnectionState::Connected && n.last_connected.map_or(true, |t| t.elapsed().as_secs() >= MAX_WAIT_FOR_ADDR_SECS)
+            // one snapshot per round, a reload takes effect from the next one
+            let settings = c.settings.get();
+            let scheduler = Scheduler::new(settings.config.retry.clone());
+            c.refresh_tip();
+            for (addr, _) in c.known_network.nodes().into_iter().filter(|(a, n)| {
+                !c.is_kept(a) && n.state == ConnectionState::Connected && n.last_connected.map_or(true, |t| t.elapsed().as_secs() >= settings.config.max_wait_for_addr_secs)
             }) { c.node().disconnect(addr).await; c.known_network.set_node_state(addr, ConnectionState::Disconnected); }
 
-            let snap = c.known_network.nodes();
-            let mut need_info = Vec::new();
-            let mut have_info = Vec::new();
-            for (addr, node) in snap.into_iter() {
-                if node.last_connected.map_or(false, |t| t.elapsed().as_secs() < RECONNECT_INTERVAL_SECS) { continue; }
-                if node.user_agent.is_none() { need_info.push(addr); } else { have_info.push(addr); }
-            }
-            {
-                let mut rng = rand::thread_rng();
-                need_info.shuffle(&mut rng);
-                have_info.shuffle(&mut rng);
+            let plan = scheduler.plan(&c.known_network.nodes(), |a, n| n.state == ConnectionState::Connected || c.is_kept(a));
+            if !plan.evict.is_empty() {
+                info!(parent: c.node().span(), "evicting {} addrs without a successful connection in {} days", plan.evict.len(), scheduler.policy.evict_after_days);
+                c.known_network.remove_nodes(&plan.evict);
             }
+            if plan.deferred > 0 { debug!(parent: c.node().span(), "{} due addrs left for the next round", plan.deferred); }
 
-            let mut targets = Vec::with_capacity(NUM_CONN_ATTEMPTS_PERIODIC);
-            targets.extend(need_info.into_iter().take(NUM_CONN_ATTEMPTS_PERIODIC));
-            targets.extend(have_info.into_iter().take(NUM_CONN_ATTEMPTS_PERIODIC.saturating_sub(targets.len())));
-
-            for addr in targets { if c.should_connect(addr) { let cc = c.clone(); tokio::spawn(async move { let _ = cc.connect(addr).await; }); } }
-            sleep(Duration::from_secs(args.crawl_interval)).await;
+            for addr in plan.targets {
+                // out of slots, the rest stay due for the next round
+                let slot = match c.reserve(addr) { Some(s) => s, None if c.num_dialing() + c.node().num_connected() >= settings.config.max_concurrent_connections.into() => break, None => continue };
+                let cc = c.clone();
+                tokio::spawn(async move { let _ = cc.connect(addr, slot).await; });
+            }
+            sleep(Duration::from_secs(settings.config.crawl_interval_secs)).await;
         }
     });
 
     let c2 = crawler.clone();
     let sum = Arc::clone(&summary);
     let nsnap = Arc::clone(&nodes_snap);
+    let csnap = Arc::clone(&conns_snap);
+    let st = store.clone();
+    let hist = Arc::clone(&history);
+    let gm = Arc::clone(&graph_metrics);
+    let g = Arc::clone(&geo);
     thread::spawn(move || {
         loop {
             let t = Instant::now();
-            c2.known_network.remove_old_connections();
+            let settings = c2.settings.get();
+            let cl = &settings.classifier;
+            c2.known_network.remove_old_connections(settings.config.last_seen_cutoff_secs);
             metrics.update_graph(&c2);
-            *sum.lock() = metrics.request_summary(&c2);
-            *nsnap.lock() = c2.known_network.nodes();
-            thread::sleep(Duration::from_secs(SUMMARY_INTERVAL).saturating_sub(t.elapsed()));
+            let s = metrics.request_summary(&c2);
+            let nodes = c2.known_network.nodes();
+            hist.lock().push(HistoryPoint::new(compute_stats(&nodes, cl, s.crawler_runtime.as_secs()), &s.protocol_versions));
+            *sum.lock() = s;
+            *gm.lock() = metrics.graph_metrics(&c2);
+            if let Some((ref dir, ref f)) = export {
+                if let Err(e) = export_nodes_to_dir(dir, &select_nodes(&nodes, cl, &g, f), network) { error!("node export to {} failed: {}", dir.display(), e); }
+            }
+            *nsnap.lock() = nodes;
+            *csnap.lock() = c2.known_network.connections();
+            if let Some(ref s) = st { if let Err(e) = s.checkpoint(&c2.known_network) { error!("db checkpoint failed: {}", e); } }
+            c2.counters.observe_summary(t.elapsed());
+            thread::sleep(Duration::from_secs(settings.config.summary_interval_secs).saturating_sub(t.elapsed()));
         }
     });
 
@@ -188,6 +415,7 @@ async fn main() {
     crawl_task.abort();
     let _ = crawl_task.await;
     crawler.node().shut_down().await;
+    if let Some(ref s) = store { if let Err(e) = s.checkpoint(&crawler.known_network) { error!("db checkpoint failed: {}", e); } }
     let s = summary.lock();
     info!(parent: crawler.node().span(), "{}", s);
     let _ = s.log_to_file(LOG_FILE);
@@ -0,0 +1,238 @@
+// mempool observation - opt in with --observe-mempool, records who announces which tx first
+// and fetches a sample of them to see which transaction versions are being relayed
+
+use std::{collections::{BTreeMap, HashMap, VecDeque}, net::SocketAddr, time::Instant};
+use serde::Serialize;
+use ziggurat_zcash::protocol::payload::{inv::InvHash, Hash, Tx};
+use crate::network::Latency;
+
+// txs remembered for first-seen and announcer counts, oldest go first
+pub const MAX_TRACKED_TXS: usize = 50_000;
+// peers with relay stats, the one that announced longest ago goes first
+pub const MAX_TRACKED_PEERS: usize = 10_000;
+// getdata requests we wait on per peer before giving up on the oldest
+const MAX_PENDING_PER_PEER: usize = 64;
+// connections kept open for relay stats besides the tip peers
+pub const OBSERVER_PEERS: usize = 64;
+
+struct TxSeen {
+    first_seen: Instant,
+    announcers: usize,
+    // announced as a zip-239 wtxid, i.e. a v5 tx
+    wtx: bool,
+    version: Option<u32>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct PeerRelay {
+    pub announced: u64,
+    // times this peer was the first to announce a tx
+    pub first: u64,
+    pub fetched: u64,
+    #[serde(skip)]
+    lag: Latency,
+    #[serde(skip)]
+    last_announced: Option<Instant>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PeerRelayInfo {
+    pub addr: SocketAddr,
+    #[serde(flatten)]
+    pub relay: PeerRelay,
+    // how far behind the first announcer this peer usually is
+    pub median_lag_ms: Option<u32>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct TxTypeStats { pub txs: u64, pub mean_announcers: f64 }
+
+#[derive(Clone, Default, Serialize)]
+pub struct RelayStats {
+    pub tracked_txs: usize,
+    pub announcements: u64,
+    pub inv_tx: u64,
+    pub inv_wtx: u64,
+    pub fetched: u64,
+    pub not_found: u64,
+    pub by_version: BTreeMap<String, TxTypeStats>,
+    pub peers: Vec<PeerRelayInfo>,
+}
+
+pub struct MempoolTracker {
+    // share of newly seen txs we ask for with getdata
+    sample_rate: f64,
+    txs: HashMap<Hash, TxSeen>,
+    order: VecDeque<Hash>,
+    peers: HashMap<SocketAddr, PeerRelay>,
+    // getdata we sent per peer, in order, with whether it was a wtx
+    pending: HashMap<SocketAddr, VecDeque<(Hash, bool)>>,
+    announcements: u64,
+    inv_tx: u64,
+    inv_wtx: u64,
+    fetched: u64,
+    not_found: u64,
+}
+
+// the id we key txs on, for v5 the txid half of the wtxid
+fn tx_key(i: &InvHash) -> Option<(Hash, bool)> {
+    match i { InvHash::Tx(h) => Some((*h, false)), InvHash::MsgWtx(w) => Some((w.id, true)), _ => None }
+}
+
+impl MempoolTracker {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate, txs: HashMap::new(), order: VecDeque::new(), peers: HashMap::new(), pending: HashMap::new(),
+            announcements: 0, inv_tx: 0, inv_wtx: 0, fetched: 0, not_found: 0,
+        }
+    }
+
+    // records the tx invs from one message, returns the ones to fetch from that peer
+    pub fn observe(&mut self, src: SocketAddr, inv: &[InvHash], now: Instant) -> Vec<InvHash> {
+        let mut fetch = Vec::new();
+        if !self.peers.contains_key(&src) && self.peers.len() >= MAX_TRACKED_PEERS {
+            if let Some(old) = self.peers.iter().min_by_key(|(_, p)| p.last_announced).map(|(a, _)| *a) { self.peers.remove(&old); }
+        }
+        for i in inv {
+            let (key, wtx) = match tx_key(i) { Some(k) => k, None => continue };
+            self.announcements += 1;
+            if wtx { self.inv_wtx += 1; } else { self.inv_tx += 1; }
+            let peer = self.peers.entry(src).or_default();
+            peer.announced += 1;
+            peer.last_announced = Some(now);
+            match self.txs.get_mut(&key) {
+                Some(t) => { t.announcers += 1; peer.lag.add(now.saturating_duration_since(t.first_seen)); }
+                None => {
+                    if self.order.len() >= MAX_TRACKED_TXS { if let Some(old) = self.order.pop_front() { self.txs.remove(&old); } }
+                    self.order.push_back(key);
+                    self.txs.insert(key, TxSeen { first_seen: now, announcers: 1, wtx, version: None });
+                    peer.first += 1;
+                    peer.lag.add(Default::default());
+                    if rand::random::<f64>() < self.sample_rate { fetch.push(*i); }
+                }
+            }
+        }
+        if fetch.is_empty() { return fetch; }
+        let q = self.pending.entry(src).or_default();
+        for i in &fetch { if let Some(k) = tx_key(i) { q.push_back(k); } }
+        while q.len() > MAX_PENDING_PER_PEER { q.pop_front(); }
+        fetch
+    }
+
+    // a tx we fetched, v1-v4 are matched by txid, v5 ids need zip-244 hashing so they match the oldest wtx request
+    pub fn record_tx(&mut self, src: SocketAddr, tx: &Tx) {
+        let version = tx.version();
+        let q = match self.pending.get_mut(&src) { Some(q) => q, None => return };
+        let pos = if version >= 5 { q.iter().position(|(_, w)| *w) } else {
+            let id = match tx.double_sha256() { Ok(h) => h, Err(_) => return };
+            q.iter().position(|(h, w)| !*w && *h == id)
+        };
+        let (key, _) = match pos.and_then(|p| q.remove(p)) { Some(k) => k, None => return };
+        if let Some(t) = self.txs.get_mut(&key) { t.version = Some(version); }
+        self.fetched += 1;
+        if let Some(p) = self.peers.get_mut(&src) { p.fetched += 1; }
+    }
+
+    pub fn not_found(&mut self, src: SocketAddr, inv: &[InvHash]) {
+        let q = match self.pending.get_mut(&src) { Some(q) => q, None => return };
+        for k in inv.iter().filter_map(tx_key) { if let Some(p) = q.iter().position(|x| *x == k) { q.remove(p); self.not_found += 1; } }
+    }
+
+    // forgets the getdata still pending from peers that went away, their replies wont come
+    pub fn retain_pending(&mut self, connected: impl Fn(&SocketAddr) -> bool) { self.pending.retain(|a, _| connected(a)); }
+
+    pub fn stats(&self) -> RelayStats {
+        let mut by_version: BTreeMap<String, (u64, usize)> = BTreeMap::new();
+        for t in self.txs.values() {
+            // wtx announcements are v5 even when we didnt fetch them
+            let v = match (t.version, t.wtx) { (Some(v), _) => v, (None, true) => 5, (None, false) => continue };
+            let e = by_version.entry(format!("v{}", v)).or_default();
+            e.0 += 1;
+            e.1 += t.announcers;
+        }
+        let mut peers: Vec<_> = self.peers.iter().map(|(a, p)| PeerRelayInfo { addr: *a, relay: p.clone(), median_lag_ms: p.lag.median_ms() }).collect();
+        peers.sort_by(|a, b| b.relay.first.cmp(&a.relay.first).then(b.relay.announced.cmp(&a.relay.announced)));
+        RelayStats {
+            tracked_txs: self.txs.len(), announcements: self.announcements, inv_tx: self.inv_tx, inv_wtx: self.inv_wtx,
+            fetched: self.fetched, not_found: self.not_found,
+            by_version: by_version.into_iter().map(|(k, (n, a))| (k, TxTypeStats { txs: n, mean_announcers: a as f64 / n as f64 })).collect(),
+            peers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ziggurat_zcash::{protocol::payload::{block::Block, codec::Codec, inv::WtxId}, vectors::{BLOCK_TESTNET_1_599_199_BYTES, BLOCK_TESTNET_1_599_200_BYTES}};
+
+    #[test]
+    fn counts_announcers_and_firsts() {
+        let mut m = MempoolTracker::new(1.0);
+        let (a, b): (SocketAddr, SocketAddr) = ("1.1.1.1:8233".parse().unwrap(), "2.2.2.2:8233".parse().unwrap());
+        let tx = InvHash::Tx(Hash::new([1; 32]));
+        let wtx = InvHash::MsgWtx(WtxId { id: Hash::new([2; 32]), auth_digest: Hash::new([3; 32]) });
+        let now = Instant::now();
+        assert_eq!(m.observe(a, &[tx, wtx], now).len(), 2);
+        // already known, nothing to fetch again
+        assert!(m.observe(b, &[tx], now).is_empty());
+        m.not_found(a, &[wtx]);
+
+        let s = m.stats();
+        assert_eq!((s.tracked_txs, s.announcements, s.inv_tx, s.inv_wtx, s.not_found), (2, 3, 2, 1, 1));
+        assert_eq!(s.by_version["v5"].txs, 1);
+        assert_eq!(s.peers[0].addr, a);
+        assert_eq!(s.peers[0].relay.first, 2);
+    }
+
+    fn coinbase(block: &[u8]) -> Tx { Block::decode(&mut std::io::Cursor::new(block)).unwrap().txs.remove(0) }
+
+    #[test]
+    fn matches_fetched_txs() {
+        let mut m = MempoolTracker::new(1.0);
+        let (a, b): (SocketAddr, SocketAddr) = ("1.1.1.1:8233".parse().unwrap(), "2.2.2.2:8233".parse().unwrap());
+        let (v4, v5) = (coinbase(&BLOCK_TESTNET_1_599_199_BYTES), coinbase(&BLOCK_TESTNET_1_599_200_BYTES));
+        assert_eq!((v4.version(), v5.version()), (4, 5));
+        let wtx = |i| InvHash::MsgWtx(WtxId { id: Hash::new([i; 32]), auth_digest: Hash::new([0; 32]) });
+        assert_eq!(m.observe(a, &[InvHash::Tx(v4.double_sha256().unwrap()), wtx(1), wtx(2)], Instant::now()).len(), 3);
+
+        // nothing was asked from b
+        m.record_tx(b, &v4);
+        assert_eq!(m.stats().fetched, 0);
+        // v5 takes the oldest wtx request, v4 its own txid
+        m.record_tx(a, &v5);
+        m.record_tx(a, &v4);
+        assert_eq!(m.txs[&Hash::new([1; 32])].version, Some(5));
+        assert_eq!(m.txs[&Hash::new([2; 32])].version, None);
+        // the v4 request is used up, another copy doesnt count
+        m.record_tx(a, &v4);
+        m.record_tx(a, &v5);
+        // no wtx request left
+        m.record_tx(a, &v5);
+
+        let s = m.stats();
+        assert_eq!(s.fetched, 3);
+        assert_eq!((s.by_version["v4"].txs, s.by_version["v5"].txs), (1, 2));
+        assert_eq!(s.peers.iter().find(|p| p.addr == a).unwrap().relay.fetched, 3);
+        assert!(m.pending[&a].is_empty());
+    }
+
+    #[test]
+    fn bounds_peers_and_pending() {
+        let mut m = MempoolTracker::new(1.0);
+        let start = Instant::now();
+        for i in 0..=MAX_TRACKED_PEERS {
+            let src = SocketAddr::from(([10, (i >> 16) as u8, (i >> 8) as u8, i as u8], 8233));
+            m.observe(src, &[InvHash::Tx(Hash::new([(i % 251) as u8; 32]))], start + std::time::Duration::from_millis(i as u64));
+        }
+        // the first announcer was the quietest for longest
+        assert_eq!(m.peers.len(), MAX_TRACKED_PEERS);
+        assert!(!m.peers.contains_key(&"10.0.0.0:8233".parse().unwrap()));
+        // only the first 251 peers saw a new tx and have getdata pending
+        assert_eq!(m.pending.len(), 251);
+
+        let kept: SocketAddr = "10.0.0.1:8233".parse().unwrap();
+        m.retain_pending(|a| *a == kept);
+        assert_eq!(m.pending.keys().collect::<Vec<_>>(), vec![&kept]);
+    }
+}
@@ -1,57 +1,46 @@
 // metrics - graph stuff and network summary
 
 use std::{collections::HashMap, net::SocketAddr};
-use regex::Regex;
 use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
-use crate::{network::{KnownNode, LAST_SEEN_CUTOFF}, Crawler};
-
-const MIN_HEIGHT: i32 = 2_000_000;
-pub const ZCASH_P2P_DEFAULT_MAINNET_PORT: u16 = 8233;
-pub const ZCASH_P2P_DEFAULT_TESTNET_PORT: u16 = 18233;
+use crate::{classifier::NodeClassifier, graph::{analyze, GraphMetrics}, network::KnownNode, Crawler};
 
+// the classifier and cutoffs come from the crawlers settings at each call, so reloads apply to the next summary
 #[derive(Default)]
 pub struct NetworkMetrics { graph: Graph<SocketAddr> }
 
 impl NetworkMetrics {
+
     pub fn update_graph(&mut self, crawler: &Crawler) {
+        let cutoff = crawler.settings.get().config.last_seen_cutoff_secs;
         for c in crawler.known_network.connections() {
             let e = Edge::new(c.a, c.b);
-            if c.last_seen.elapsed().as_secs() > LAST_SEEN_CUTOFF { self.graph.remove(&e); }
+            if c.last_seen.elapsed().as_secs() > cutoff { self.graph.remove(&e); }
             else { self.graph.insert(e); }
         }
     }
 
     pub fn request_summary(&mut self, crawler: &Crawler) -> NetworkSummary {
-        build_summary(crawler, &self.graph)
+        build_summary(crawler, &self.graph, &crawler.settings.get().classifier)
     }
-}
-
-fn classify_nodes(nodes: &HashMap<SocketAddr, KnownNode>, good: &[SocketAddr]) -> Vec<NetworkType> {
-    let zc_re = Regex::new(r"^/MagicBean:(\d)\.(\d)\.(\d)/$").unwrap();
-    let zb_re = Regex::new(r"^/Zebra:(\d)\.(\d)\.(\d)").unwrap();
 
-    good.iter().map(|addr| {
-        let n = &nodes[addr];
-        let agent = n.user_agent.as_ref().map(|x| x.0.clone()).unwrap_or_default();
-        let port_ok = addr.port() == ZCASH_P2P_DEFAULT_MAINNET_PORT || addr.port() == ZCASH_P2P_DEFAULT_TESTNET_PORT;
-
-        // check zcashd
-        let mut agent_ok = false;
-        if let Some(cap) = zc_re.captures(&agent) {
-            let major: u32 = cap.get(1).unwrap().as_str().parse().unwrap_or(0);
-            if major >= 6 { return NetworkType::Unknown; } // flux
-            agent_ok = true;
-        }
-        if zb_re.is_match(&agent) { agent_ok = true; }
+    // analytics over the relevant nodes and the recent connections between them
+    pub fn graph_metrics(&self, crawler: &Crawler) -> GraphMetrics {
+        let settings = crawler.settings.get();
+        let nodes = crawler.known_network.nodes();
+        let tip = settings.classifier.tip(&nodes);
+        let good: Vec<_> = nodes.iter().filter(|(a, n)| settings.classifier.is_good_node(a, n, tip, None)).map(|(a, _)| *a).collect();
+        let edges: Vec<_> = crawler.known_network.connections().into_iter().filter(|c| c.last_seen.elapsed().as_secs() <= settings.config.last_seen_cutoff_secs).map(|c| (c.a, c.b)).collect();
+        analyze(&good, &edges)
+    }
+}
 
-        let h = n.start_height.unwrap_or(0);
-        if h < MIN_HEIGHT { return NetworkType::Unknown; }
-        if port_ok || agent_ok { NetworkType::Zcash } else { NetworkType::Unknown }
-    }).collect()
+fn classify_nodes(cl: &NodeClassifier, nodes: &HashMap<SocketAddr, KnownNode>, good: &[SocketAddr]) -> Vec<NetworkType> {
+    let tip = cl.tip(nodes);
+    good.iter().map(|addr| if cl.is_good_node(addr, &nodes[addr], tip, None) { NetworkType::Zcash } else { NetworkType::Unknown }).collect()
 }
 
-fn build_summary(crawler: &Crawler, graph: &Graph<SocketAddr>) -> NetworkSummary {
+fn build_summary(crawler: &Crawler, graph: &Graph<SocketAddr>, cl: &NodeClassifier) -> NetworkSummary {
     let nodes = crawler.known_network.nodes();
     let conns = crawler.known_network.connections();
 
@@ -66,7 +55,7 @@ fn build_summary(crawler: &Crawler, graph: &Graph<SocketAddr>) -> NetworkSummary
         }
     }
 
-    let types = classify_nodes(&nodes, &good);
+    let types = classify_nodes(cl, &nodes, &good);
 
     // build adjacency manually
     let indices: Vec<Vec<usize>> = good.iter().enumerate().map(|(_, a)| {
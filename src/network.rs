@@ -1,39 +1,223 @@
 // network state - keeps track of nodes we know about
 
-use std::{collections::{HashMap, HashSet}, net::SocketAddr, time::{Duration, Instant}};
+use std::{collections::{HashMap, HashSet, VecDeque}, fmt, net::SocketAddr, str::FromStr, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use ziggurat_core_crawler::connection::KnownConnection;
-use ziggurat_zcash::protocol::payload::{ProtocolVersion, VarStr};
-
-pub const LAST_SEEN_CUTOFF: u64 = 600; // 10 min
+use ziggurat_zcash::protocol::payload::{ProtocolVersion, ServiceFlags, VarStr};
+use crate::user_agent::UserAgent;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub enum ConnectionState { #[default] Disconnected, Connected }
 
+// uptime windows in secs, same ones the bitcoin seeder reports
+pub const UPTIME_WINDOWS: [u64; 5] = [2 * 3600, 8 * 3600, 86_400, 7 * 86_400, 30 * 86_400];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UptimeWindow {
+    #[serde(rename = "2h")] H2,
+    #[serde(rename = "8h")] H8,
+    #[serde(rename = "1d")] D1,
+    #[serde(rename = "7d")] D7,
+    #[serde(rename = "30d")] D30,
+}
+
+impl UptimeWindow {
+    pub fn name(&self) -> &'static str {
+        match self { Self::H2 => "2h", Self::H8 => "8h", Self::D1 => "1d", Self::D7 => "7d", Self::D30 => "30d" }
+    }
+}
+
+impl FromStr for UptimeWindow {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Self::H2, Self::H8, Self::D1, Self::D7, Self::D30].into_iter().find(|w| w.name() == s).ok_or_else(|| format!("unknown uptime window {:?}, expected 2h, 8h, 1d, 7d or 30d", s))
+    }
+}
+
+// exponentially decaying connection stats for one window
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ReachStat { pub weight: f64, pub count: f64, pub reliability: f64 }
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Reachability {
+    pub last_update: u64,
+    pub stats: [ReachStat; 5],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UptimeScores {
+    #[serde(rename = "2h")] pub h2: f64,
+    #[serde(rename = "8h")] pub h8: f64,
+    #[serde(rename = "1d")] pub d1: f64,
+    #[serde(rename = "7d")] pub d7: f64,
+    #[serde(rename = "30d")] pub d30: f64,
+}
+
+pub fn unix_now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) }
+
+impl Reachability {
+    // every attempt decays the old stats by how long ago the previous one was, a first attempt counts fully
+    pub fn update(&mut self, good: bool, now: u64) {
+        let age = now.saturating_sub(self.last_update) as f64;
+        for (s, tau) in self.stats.iter_mut().zip(UPTIME_WINDOWS) {
+            let f = if self.last_update == 0 { 0.0 } else { (-age / tau as f64).exp() };
+            s.reliability = s.reliability * f + if good { 1.0 - f } else { 0.0 };
+            s.count = s.count * f + 1.0;
+            s.weight = s.weight * f + (1.0 - f);
+        }
+        self.last_update = now;
+    }
+
+    // fraction of successful attempts in the window, 0 if we never tried
+    pub fn score(&self, w: UptimeWindow) -> f64 {
+        let s = self.stats[w as usize];
+        if s.weight > 0.0 { s.reliability / s.weight } else { 0.0 }
+    }
+
+    pub fn scores(&self) -> UptimeScores {
+        UptimeScores { h2: self.score(UptimeWindow::H2), h8: self.score(UptimeWindow::H8), d1: self.score(UptimeWindow::D1),
+            d7: self.score(UptimeWindow::D7), d30: self.score(UptimeWindow::D30) }
+    }
+}
+
+// ping rtt samples, only the recent ones are kept so the median follows route changes
+pub const LATENCY_SAMPLES: usize = 16;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Latency { pub samples: VecDeque<u32>, pub min_ms: Option<u32> }
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LatencySummary { pub min_ms: u32, pub median_ms: u32, pub last_ms: u32 }
+
+impl Latency {
+    pub fn add(&mut self, rtt: Duration) {
+        let ms = rtt.as_millis().min(u32::MAX as u128) as u32;
+        if self.samples.len() >= LATENCY_SAMPLES { self.samples.pop_front(); }
+        self.samples.push_back(ms);
+        self.min_ms = Some(self.min_ms.map_or(ms, |m| m.min(ms)));
+    }
+
+    pub fn median_ms(&self) -> Option<u32> {
+        let mut s: Vec<_> = self.samples.iter().copied().collect();
+        s.sort_unstable();
+        s.get(s.len() / 2).copied()
+    }
+
+    pub fn summary(&self) -> Option<LatencySummary> {
+        Some(LatencySummary { min_ms: self.min_ms?, median_ms: self.median_ms()?, last_ms: *self.samples.back()? })
+    }
+}
+
+// why a connection attempt didnt end in a completed version/verack exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeFailure { Unreachable, Timeout, Reject, WrongMagic, EarlyDisconnect, ProtocolError }
+
+impl HandshakeFailure {
+    pub const ALL: [Self; 6] = [Self::Unreachable, Self::Timeout, Self::Reject, Self::WrongMagic, Self::EarlyDisconnect, Self::ProtocolError];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unreachable => "unreachable", Self::Timeout => "timeout", Self::Reject => "reject",
+            Self::WrongMagic => "wrong_magic", Self::EarlyDisconnect => "early_disconnect", Self::ProtocolError => "protocol_error",
+        }
+    }
+}
+
+impl fmt::Display for HandshakeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.name()) }
+}
+
+impl std::error::Error for HandshakeFailure {}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FailureCounts { pub unreachable: u32, pub timeout: u32, pub reject: u32, pub wrong_magic: u32, pub early_disconnect: u32, pub protocol_error: u32 }
+
+impl FailureCounts {
+    pub fn add(&mut self, f: HandshakeFailure) {
+        let c = match f {
+            HandshakeFailure::Unreachable => &mut self.unreachable, HandshakeFailure::Timeout => &mut self.timeout,
+            HandshakeFailure::Reject => &mut self.reject, HandshakeFailure::WrongMagic => &mut self.wrong_magic,
+            HandshakeFailure::EarlyDisconnect => &mut self.early_disconnect, HandshakeFailure::ProtocolError => &mut self.protocol_error,
+        };
+        *c = c.saturating_add(1);
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct KnownNode {
     pub last_connected: Option<Instant>,
     pub handshake_time: Option<Duration>,
     pub protocol_version: Option<ProtocolVersion>,
     pub user_agent: Option<VarStr>,
+    pub parsed_user_agent: Option<UserAgent>,
     pub start_height: Option<i32>,
-    pub services: Option<u64>,
-    pub connection_failures: u8,
+    pub services: Option<ServiceFlags>,
+    // what other peers last claimed this node offers in addr gossip
+    pub gossip_services: Option<ServiceFlags>,
+    // consecutive failed attempts, reset on success, drives the retry backoff
+    pub connection_failures: u32,
+    // unix secs when the addr first entered the table
+    pub first_seen: Option<u64>,
+    // last time a peer gossiped this addr to us
+    pub last_gossip: Option<Instant>,
+    pub handshake_failures: FailureCounts,
+    pub last_failure: Option<HandshakeFailure>,
+    // timestamp and nonce from the peers own version message
+    pub remote_timestamp: Option<i64>,
+    pub remote_nonce: Option<u64>,
+    // the address the peer thinks it has, from addr_from in its version
+    pub remote_addr_from: Option<SocketAddr>,
+    pub latency: Latency,
+    // how far behind the first announcer this node announces new blocks, only kept for tip peers
+    pub announce_lag: Latency,
+    // their version timestamp minus our clock when it arrived, in secs
+    pub clock_offset: Option<i64>,
+    // height of the newest block this tip peer announced or sent us headers up to
+    pub announced_height: Option<i32>,
     pub state: ConnectionState,
+    pub reachability: Reachability,
 }
 
 #[derive(Default)]
 pub struct KnownNetwork {
     pub nodes: RwLock<HashMap<SocketAddr, KnownNode>>,
     pub connections: RwLock<HashSet<KnownConnection>>,
+    // tor/i2p/cjdns addrs from addrv2 gossip, we cant dial them but still want to count them
+    pub overlay_addrs: RwLock<HashMap<String, Instant>>,
 }
 
 impl KnownNetwork {
     pub fn add_addrs(&self, src: SocketAddr, addrs: &[SocketAddr]) {
         { let mut c = self.connections.write(); for a in addrs { c.insert(KnownConnection::new(src, *a)); } }
+        let (now, ts) = (Instant::now(), unix_now());
+        let mut n = self.nodes.write();
+        n.entry(src).or_insert_with(|| KnownNode { first_seen: Some(ts), ..Default::default() });
+        for a in addrs { n.entry(*a).or_insert_with(|| KnownNode { first_seen: Some(ts), ..Default::default() }).last_gossip = Some(now); }
+    }
+
+    // true if the addr wasnt known yet
+    pub fn add_node(&self, addr: SocketAddr) -> bool {
         let mut n = self.nodes.write();
-        n.entry(src).or_default();
-        for a in addrs { n.entry(*a).or_default(); }
+        if n.contains_key(&addr) { return false; }
+        n.insert(addr, KnownNode { first_seen: Some(unix_now()), ..Default::default() });
+        true
+    }
+
+    pub fn remove_nodes(&self, addrs: &[SocketAddr]) {
+        let mut n = self.nodes.write();
+        for a in addrs { n.remove(a); }
+    }
+
+    pub fn add_gossip_services(&self, addrs: &[(SocketAddr, ServiceFlags)]) {
+        let mut n = self.nodes.write();
+        for (a, s) in addrs { if let Some(node) = n.get_mut(a) { node.gossip_services = Some(*s); } }
+    }
+
+    pub fn add_overlay_addrs(&self, addrs: &[String]) {
+        let mut o = self.overlay_addrs.write();
+        for a in addrs { o.insert(a.clone(), Instant::now()); }
     }
 
     pub fn set_node_state(&self, addr: SocketAddr, state: ConnectionState) {
@@ -45,12 +229,51 @@ impl KnownNetwork {
     #[allow(dead_code)]
     pub fn num_connections(&self) -> usize { self.connections.read().len() }
     pub fn num_nodes(&self) -> usize { self.nodes.read().len() }
+    pub fn num_overlay_addrs(&self) -> usize { self.overlay_addrs.read().len() }
 
-    pub fn remove_old_connections(&self) {
-        let old: Vec<_> = self.connections().into_iter().filter(|c| c.last_seen.elapsed().as_secs() > LAST_SEEN_CUTOFF).collect();
+    // also forgets overlay addrs nobody gossiped within max_age
+    pub fn remove_old_connections(&self, max_age: u64) {
+        let old: Vec<_> = self.connections().into_iter().filter(|c| c.last_seen.elapsed().as_secs() > max_age).collect();
         if !old.is_empty() {
             let mut c = self.connections.write();
             for x in old { c.remove(&x); }
         }
+        self.overlay_addrs.write().retain(|_, t| t.elapsed().as_secs() <= max_age);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_keeps_recent_samples() {
+        let mut l = Latency::default();
+        assert!(l.summary().is_none());
+        for ms in (1..=20).rev() { l.add(Duration::from_millis(ms * 10)); }
+        let s = l.summary().unwrap();
+        assert_eq!(l.samples.len(), LATENCY_SAMPLES);
+        assert_eq!((s.min_ms, s.last_ms), (10, 10));
+        assert_eq!(s.median_ms, 90);
+    }
+
+    #[test]
+    fn reachability_decays() {
+        let mut r = Reachability::default();
+        r.update(true, 1_000_000);
+        assert_eq!(r.score(UptimeWindow::H2), 1.0);
+        // a failure 2h later drags the 2h window down but barely moves the 30d one
+        r.update(false, 1_000_000 + 7200);
+        assert!(r.score(UptimeWindow::H2) < 0.4);
+        assert!(r.score(UptimeWindow::D30) > 0.99);
+    }
+
+    #[test]
+    fn prunes_old_overlay_addrs() {
+        let net = KnownNetwork::default();
+        net.add_overlay_addrs(&["new.onion:8233".into()]);
+        net.overlay_addrs.write().insert("old.onion:8233".into(), Instant::now() - Duration::from_secs(5));
+        net.remove_old_connections(2);
+        assert_eq!(net.overlay_addrs.read().keys().collect::<Vec<_>>(), vec!["new.onion:8233"]);
     }
 }
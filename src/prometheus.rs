@@ -0,0 +1,165 @@
+// prometheus exporter - serves crawler counters and node gauges as text on GET /metrics
+
+use std::{collections::HashMap, fmt::Write as _, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use parking_lot::Mutex;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+use tracing::{debug, error, info};
+use crate::{config::SharedSettings, network::{HandshakeFailure, KnownNode}};
+
+// upper bounds in seconds, +Inf is implied
+const HANDSHAKE_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0];
+const MAX_REQUEST_SIZE: usize = 8192;
+// a client that never finishes its headers doesnt get to hold a task forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct CrawlerCounters {
+    pub connection_attempts: AtomicU64,
+    pub connection_failures: AtomicU64,
+    pub addr_messages: AtomicU64,
+    pub addrs_received: AtomicU64,
+    pub summary_runs: AtomicU64,
+    pub last_summary_duration_us: AtomicU64,
+    pub last_summary_unix: AtomicU64,
+    handshake_buckets: [AtomicU64; HANDSHAKE_BUCKETS.len()],
+    handshake_sum_us: AtomicU64,
+    handshake_count: AtomicU64,
+    handshake_failures: [AtomicU64; HandshakeFailure::ALL.len()],
+}
+
+impl CrawlerCounters {
+    pub fn observe_handshake(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        for (i, le) in HANDSHAKE_BUCKETS.iter().enumerate() { if secs <= *le { self.handshake_buckets[i].fetch_add(1, Ordering::Relaxed); } }
+        self.handshake_sum_us.fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+        self.handshake_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_handshake_failure(&self, f: HandshakeFailure) {
+        self.handshake_failures[f as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_summary(&self, d: Duration) {
+        self.summary_runs.fetch_add(1, Ordering::Relaxed);
+        self.last_summary_duration_us.store(d.as_micros() as u64, Ordering::Relaxed);
+        self.last_summary_unix.store(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs()), Ordering::Relaxed);
+    }
+}
+
+pub struct MetricsExporter {
+    pub counters: Arc<CrawlerCounters>,
+    pub nodes: Arc<Mutex<HashMap<SocketAddr, KnownNode>>>,
+    pub settings: SharedSettings,
+    pub start_time: Instant,
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl MetricsExporter {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let c = &self.counters;
+
+        {
+            let classifier = Arc::clone(&self.settings.get().classifier);
+            let nodes = self.nodes.lock();
+            let tip = classifier.tip(&nodes);
+            let mut contacted = 0; let mut relevant = 0;
+            let mut types: HashMap<String, usize> = classifier.client_names().into_iter().map(|t| (t, 0)).collect();
+            for (a, n) in nodes.iter() {
+                if n.user_agent.is_none() { continue; }
+                contacted += 1;
+                let cl = classifier.classify(a, n, tip, None);
+                if cl.is_relevant() { relevant += 1; }
+                *types.entry(cl.client).or_default() += 1;
+            }
+            metric(&mut out, "znodes_known_nodes", "gauge", "Addresses the crawler knows about.");
+            let _ = writeln!(out, "znodes_known_nodes {}", nodes.len());
+            metric(&mut out, "znodes_contacted_nodes", "gauge", "Nodes that completed a version exchange.");
+            let _ = writeln!(out, "znodes_contacted_nodes {}", contacted);
+            metric(&mut out, "znodes_relevant_nodes", "gauge", "Contacted nodes that pass the zcash node filters.");
+            let _ = writeln!(out, "znodes_relevant_nodes {}", relevant);
+            metric(&mut out, "znodes_nodes_by_client", "gauge", "Contacted nodes per client type.");
+            let mut types: Vec<_> = types.into_iter().collect();
+            types.sort();
+            for (t, n) in types { let _ = writeln!(out, "znodes_nodes_by_client{{client=\"{}\"}} {}", t, n); }
+            metric(&mut out, "znodes_tip_height_estimate", "gauge", "Estimated chain tip height.");
+            let _ = writeln!(out, "znodes_tip_height_estimate {}", tip);
+        }
+
+        metric(&mut out, "znodes_connection_attempts_total", "counter", "Outbound connection attempts.");
+        let _ = writeln!(out, "znodes_connection_attempts_total {}", load(&c.connection_attempts));
+        metric(&mut out, "znodes_connection_failures_total", "counter", "Outbound connection attempts that failed.");
+        let _ = writeln!(out, "znodes_connection_failures_total {}", load(&c.connection_failures));
+        metric(&mut out, "znodes_addr_messages_total", "counter", "Addr and addrv2 messages received.");
+        let _ = writeln!(out, "znodes_addr_messages_total {}", load(&c.addr_messages));
+        metric(&mut out, "znodes_addrs_received_total", "counter", "Addresses received in addr and addrv2 messages.");
+        let _ = writeln!(out, "znodes_addrs_received_total {}", load(&c.addrs_received));
+
+        metric(&mut out, "znodes_handshake_failures_total", "counter", "Failed connection attempts by reason.");
+        for f in HandshakeFailure::ALL { let _ = writeln!(out, "znodes_handshake_failures_total{{reason=\"{}\"}} {}", f, load(&c.handshake_failures[f as usize])); }
+
+        metric(&mut out, "znodes_handshake_duration_seconds", "histogram", "Time from sending our version to having the peers version and verack.");
+        for (i, le) in HANDSHAKE_BUCKETS.iter().enumerate() {
+            let _ = writeln!(out, "znodes_handshake_duration_seconds_bucket{{le=\"{}\"}} {}", le, load(&c.handshake_buckets[i]));
+        }
+        let _ = writeln!(out, "znodes_handshake_duration_seconds_bucket{{le=\"+Inf\"}} {}", load(&c.handshake_count));
+        let _ = writeln!(out, "znodes_handshake_duration_seconds_sum {}", load(&c.handshake_sum_us) as f64 / 1e6);
+        let _ = writeln!(out, "znodes_handshake_duration_seconds_count {}", load(&c.handshake_count));
+
+        metric(&mut out, "znodes_summary_runs_total", "counter", "Completed summary loop iterations.");
+        let _ = writeln!(out, "znodes_summary_runs_total {}", load(&c.summary_runs));
+        metric(&mut out, "znodes_summary_duration_seconds", "gauge", "Duration of the last summary loop iteration.");
+        let _ = writeln!(out, "znodes_summary_duration_seconds {}", load(&c.last_summary_duration_us) as f64 / 1e6);
+        metric(&mut out, "znodes_last_summary_timestamp_seconds", "gauge", "Unix time the last summary loop iteration finished.");
+        let _ = writeln!(out, "znodes_last_summary_timestamp_seconds {}", load(&c.last_summary_unix));
+        metric(&mut out, "znodes_uptime_seconds", "gauge", "Seconds since the crawler started.");
+        let _ = writeln!(out, "znodes_uptime_seconds {}", self.start_time.elapsed().as_secs());
+        out
+    }
+}
+
+async fn read_request(stream: &mut TcpStream, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    // only the request line matters, read until the end of the headers
+    while len < buf.len() && !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 { break; }
+        len += n;
+    }
+    Ok(len)
+}
+
+async fn handle(mut stream: TcpStream, exporter: &MetricsExporter) -> std::io::Result<()> {
+    let mut buf = vec![0u8; MAX_REQUEST_SIZE];
+    let len = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream, &mut buf)).await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request read timed out"))??;
+    let req = String::from_utf8_lossy(&buf[..len]);
+    let path = req.lines().next().and_then(|l| { let mut p = l.split_whitespace(); if p.next() == Some("GET") { p.next() } else { None } });
+
+    let (status, body) = match path {
+        Some("/metrics") => ("200 OK", exporter.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let resp = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+pub async fn serve_metrics(addr: SocketAddr, exporter: MetricsExporter) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => { error!("cant bind metrics endpoint {}: {}", addr, e); return; }
+    };
+    info!("metrics at http://{}/metrics", addr);
+    let exporter = Arc::new(exporter);
+    loop {
+        let (stream, peer) = match listener.accept().await { Ok(x) => x, Err(e) => { debug!("metrics accept failed: {}", e); continue; } };
+        let ex = Arc::clone(&exporter);
+        tokio::spawn(async move { if let Err(e) = handle(stream, &ex).await { debug!("metrics request from {} failed: {}", peer, e); } });
+    }
+}
@@ -0,0 +1,117 @@
+// block propagation - when each tip peer first announced each new block, by inv, headers or a pushed block
+// curves are relative to the first announcement we saw, so they measure spread between peers not from the miner
+
+use std::{collections::{BTreeMap, HashMap, VecDeque}, net::SocketAddr, time::{Duration, Instant}};
+use serde::Serialize;
+use ziggurat_zcash::protocol::payload::Hash;
+use crate::{chain::HeaderChain, network::unix_now};
+
+// blocks kept, at 75s a block this is a bit over two hours
+pub const MAX_TRACKED_BLOCKS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnounceSource { Inv, Headers, Block }
+
+impl AnnounceSource {
+    pub fn name(&self) -> &'static str { match self { Self::Inv => "inv", Self::Headers => "headers", Self::Block => "block" } }
+}
+
+struct BlockSeen {
+    first_seen: Instant,
+    first_seen_unix: u64,
+    // tip peers connected when the block first showed up, the denominator for the curve
+    peers: usize,
+    announcements: Vec<(SocketAddr, Duration, AnnounceSource)>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct BlockPropagation {
+    pub hash: String,
+    pub height: Option<i32>,
+    pub first_seen: u64,
+    pub first_peer: Option<SocketAddr>,
+    pub peers: usize,
+    pub announced: usize,
+    pub by_source: BTreeMap<&'static str, usize>,
+    // time from the first announcement until half / 90% of the peers had announced, None if they never did
+    pub t50_ms: Option<u64>,
+    pub t90_ms: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct PropagationTracker {
+    blocks: HashMap<Hash, BlockSeen>,
+    order: VecDeque<Hash>,
+}
+
+// how long until frac of peers had announced, delays have to be sorted
+fn time_to(frac: f64, delays: &[Duration], peers: usize) -> Option<u64> {
+    let need = ((frac * peers as f64).ceil() as usize).max(1);
+    delays.get(need - 1).map(|d| d.as_millis() as u64)
+}
+
+impl PropagationTracker {
+    // new_block says whether the hash was unknown to the chain, blocks we already had arent started on.
+    // returns the peers delay behind the first announcement, only for its first announcement of the block
+    pub fn observe(&mut self, hash: Hash, peer: SocketAddr, source: AnnounceSource, now: Instant, new_block: bool, peers: usize) -> Option<Duration> {
+        if !self.blocks.contains_key(&hash) {
+            if !new_block { return None; }
+            if self.order.len() >= MAX_TRACKED_BLOCKS { if let Some(old) = self.order.pop_front() { self.blocks.remove(&old); } }
+            self.order.push_back(hash);
+            self.blocks.insert(hash, BlockSeen { first_seen: now, first_seen_unix: unix_now(), peers, announcements: Vec::new() });
+        }
+        let b = self.blocks.get_mut(&hash)?;
+        if b.announcements.iter().any(|(a, _, _)| *a == peer) { return None; }
+        let delay = now.saturating_duration_since(b.first_seen);
+        b.announcements.push((peer, delay, source));
+        Some(delay)
+    }
+
+    // newest first
+    pub fn report(&self, chain: &HeaderChain, count: usize) -> Vec<BlockPropagation> {
+        self.order.iter().rev().take(count).filter_map(|h| {
+            let b = self.blocks.get(h)?;
+            let mut delays: Vec<_> = b.announcements.iter().map(|(_, d, _)| *d).collect();
+            delays.sort();
+            let peers = b.peers.max(b.announcements.len());
+            let mut by_source = BTreeMap::new();
+            for (_, _, s) in &b.announcements { *by_source.entry(s.name()).or_default() += 1; }
+            Some(BlockPropagation {
+                hash: h.to_string(), height: chain.height_of(h), first_seen: b.first_seen_unix,
+                first_peer: b.announcements.first().map(|(a, _, _)| *a), peers, announced: b.announcements.len(), by_source,
+                t50_ms: time_to(0.5, &delays, peers), t90_ms: time_to(0.9, &delays, peers),
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Checkpoint;
+    use ziggurat_zcash::protocol::network::Network;
+
+    #[test]
+    fn curves_and_first_announcements() {
+        let mut t = PropagationTracker::default();
+        let h = Hash::new([1; 32]);
+        let start = Instant::now();
+        let peer = |i: u8| SocketAddr::from(([10, 0, 0, i], 8233));
+        for i in 0..4u8 {
+            let d = t.observe(h, peer(i), AnnounceSource::Inv, start + Duration::from_millis(100 * i as u64), true, 5);
+            assert_eq!(d, Some(Duration::from_millis(100 * i as u64)));
+        }
+        // a second announcement by the same peer doesnt count
+        assert_eq!(t.observe(h, peer(0), AnnounceSource::Headers, start + Duration::from_secs(1), true, 5), None);
+        // neither do blocks the chain already had when first seen
+        assert_eq!(t.observe(Hash::new([2; 32]), peer(0), AnnounceSource::Inv, start, false, 5), None);
+
+        let r = t.report(&HeaderChain::new(Checkpoint::genesis(Network::Mainnet), Network::Mainnet), 10);
+        assert_eq!(r.len(), 1);
+        assert_eq!((r[0].announced, r[0].peers), (4, 5));
+        assert_eq!(r[0].t50_ms, Some(200));
+        // 90% of 5 peers is 5 announcements, only 4 came
+        assert_eq!(r[0].t90_ms, None);
+    }
+}
@@ -1,24 +1,52 @@
 // p2p protocol stuff - handshake, message handling
 
-use std::{io, net::SocketAddr, sync::Arc, time::Instant};
-use futures_util::SinkExt;
+use std::{collections::{HashMap, HashSet}, io, net::SocketAddr, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+use parking_lot::{Mutex, RwLock};
 use pea2pea::{protocols::{Handshake, Reading, Writing}, Config, Connection, ConnectionSide, Node as Pea2PeaNode, Pea2Pea};
-use tokio_util::codec::Framed;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, time::sleep};
+use tokio_util::{bytes::BytesMut, codec::{Decoder, Encoder}};
 use tracing::*;
-use ziggurat_zcash::{protocol::{message::Message, payload::{block::Headers, Addr, VarStr, Version}}, tools::synthetic_node::MessageCodec};
+use ziggurat_zcash::{protocol::{message::{constants::{HEADER_LEN, MAX_MESSAGE_LEN}, Message}, network::{Network, WrongMagic}, payload::{block::Headers, inv::InvHash, Addr, Hash, Inv, Nonce, ServiceFlags, VarStr, Version}}, tools::synthetic_node::MessageCodec};
 use super::network::KnownNetwork;
-use crate::network::ConnectionState;
+use crate::{chain::{HeaderChain, MAX_HEADERS_PER_MSG}, config::SharedSettings, mempool::MempoolTracker, network::{unix_now, ConnectionState, HandshakeFailure}, prometheus::CrawlerCounters, propagation::{AnnounceSource, PropagationTracker}, user_agent::UserAgent};
 
-pub const NUM_CONN_ATTEMPTS_PERIODIC: usize = 2000;
-pub const MAX_CONCURRENT_CONNECTIONS: u16 = 3500;
-pub const RECONNECT_INTERVAL_SECS: u64 = 45;
-pub const MAX_WAIT_FOR_ADDR_SECS: u64 = 90;
+// a bit under Handshake::TIMEOUT_MS so a slow peer shows up as a timeout of ours, not a dropped handshake
+const HANDSHAKE_REPLY_TIMEOUT_MS: u64 = 1800;
+// pings sent while a connection waits for addrs
+const PINGS_PER_CONNECTION: usize = 3;
+const PING_INTERVAL_SECS: u64 = 10;
 
 #[derive(Clone)]
 pub struct Crawler {
     node: Pea2PeaNode,
     pub known_network: Arc<KnownNetwork>,
     pub start_time: Instant,
+    pub network: Network,
+    pub counters: Arc<CrawlerCounters>,
+    // the outstanding ping per peer, (nonce, sent at)
+    pings: Arc<Mutex<HashMap<SocketAddr, (Nonce, Instant)>>>,
+    pub chain: Arc<RwLock<HeaderChain>>,
+    // peers kept connected to follow the chain and time block announcements, they skip the addr disconnect
+    tip_peers: Arc<Mutex<HashSet<SocketAddr>>>,
+    max_tip_peers: usize,
+    // getheaders sent to each tip peer and not answered yet
+    solicited: Arc<Mutex<HashMap<SocketAddr, u32>>>,
+    pub propagation: Arc<Mutex<PropagationTracker>>,
+    // set in mempool observation mode
+    pub mempool: Option<Arc<Mutex<MempoolTracker>>>,
+    // in mempool observation mode, peers kept connected for relay stats like the tip peers
+    observers: Arc<Mutex<HashSet<SocketAddr>>>,
+    max_observers: usize,
+    pub settings: SharedSettings,
+    // connects reserved and not finished yet, together with the open connections they make up the cap
+    dialing: Arc<AtomicUsize>,
+}
+
+// a reserved connection slot, given back when the connect attempt is over
+pub struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
 }
 
 impl Pea2Pea for Crawler {
@@ -26,32 +54,175 @@ impl Pea2Pea for Crawler {
 }
 
 impl Crawler {
-    pub async fn new() -> Self {
-        let cfg = Config { name: Some("crawler".into()), listener_ip: None, max_connections: MAX_CONCURRENT_CONNECTIONS, ..Default::default() };
-        Self { node: Pea2PeaNode::new(cfg), known_network: Default::default(), start_time: Instant::now() }
+    pub async fn new(network: Network, chain: Arc<RwLock<HeaderChain>>, max_tip_peers: usize, settings: SharedSettings) -> Self {
+        // pea2peas own cap is fixed once the node exists, the reloadable cap is kept by reserve
+        let cfg = Config { name: Some("crawler".into()), listener_ip: None, max_connections: u16::MAX, ..Default::default() };
+        Self { node: Pea2PeaNode::new(cfg), known_network: Default::default(), start_time: Instant::now(), network, counters: Default::default(), pings: Default::default(), chain,
+            tip_peers: Default::default(), max_tip_peers, solicited: Default::default(), propagation: Default::default(), mempool: None,
+            observers: Default::default(), max_observers: 0, settings, dialing: Default::default() }
     }
 
-    pub async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+    // every tx inv is recorded, tip peers and up to max_observers others stay connected and get asked for their mempool
+    pub fn with_mempool(mut self, m: Arc<Mutex<MempoolTracker>>, max_observers: usize) -> Self { self.mempool = Some(m); self.max_observers = max_observers; self }
+
+    // the slot comes from reserve and is held until the attempt is over
+    pub async fn connect(&self, addr: SocketAddr, _slot: Slot) -> io::Result<()> {
         trace!(parent: self.node().span(), "connecting to {}", addr);
         let ts = Instant::now();
+        self.counters.connection_attempts.fetch_add(1, Ordering::Relaxed);
         let res = self.node.connect(addr).await;
+        let failure = res.as_ref().err().map(failure_reason);
+        if let Some(f) = failure {
+            self.counters.connection_failures.fetch_add(1, Ordering::Relaxed);
+            self.counters.observe_handshake_failure(f);
+        }
         if let Some(n) = self.known_network.nodes.write().get_mut(&addr) {
-            match res {
-                Ok(_) => { n.connection_failures = 0; n.last_connected = Some(ts); n.handshake_time = Some(ts.elapsed()); n.state = ConnectionState::Connected; }
-                Err(_) => { n.connection_failures += 1; }
+            n.reachability.update(res.is_ok(), unix_now());
+            match failure {
+                None => { n.connection_failures = 0; n.last_connected = Some(ts); n.state = ConnectionState::Connected; }
+                Some(f) => { n.connection_failures = n.connection_failures.saturating_add(1); n.handshake_failures.add(f); n.last_failure = Some(f); }
             }
         }
+        // only ask for addrs once the handshake is done and the reading protocol owns the stream
+        if res.is_ok() {
+            if let Ok(rx) = self.unicast(addr, Message::GetAddr) { let _ = rx.await; }
+            let c = self.clone();
+            tokio::spawn(async move { c.probe_latency(addr).await });
+            self.maybe_follow_tip(addr);
+            self.maybe_observe(addr);
+        }
         res
     }
 
-    pub fn should_connect(&self, addr: SocketAddr) -> bool {
-        if self.known_network.nodes().get(&addr).is_none() { return false; }
-        if self.node().num_connected() + self.node().num_connecting() >= MAX_CONCURRENT_CONNECTIONS.into() { return false; }
-        if self.node().is_connected(addr) || self.node().is_connecting(addr) { return false; }
-        true
+    fn maybe_observe(&self, addr: SocketAddr) {
+        if self.mempool.is_none() || self.is_tip_peer(&addr) { return; }
+        { let mut o = self.observers.lock(); if o.len() >= self.max_observers || !o.insert(addr) { return; } }
+        debug!(parent: self.node().span(), "observing relay through {}", addr);
+        let _ = self.unicast(addr, Message::MemPool);
+    }
+
+    // tip peers and observers stay connected past the addr exchange
+    pub fn is_kept(&self, addr: &SocketAddr) -> bool { self.is_tip_peer(addr) || self.observers.lock().contains(addr) }
+
+    // full nodes at or past our tip get kept around as tip peers while there are free slots
+    fn maybe_follow_tip(&self, addr: SocketAddr) {
+        let tip = self.chain.read().tip().height;
+        let suitable = self.known_network.nodes.read().get(&addr).map_or(false, |n| {
+            n.services.map_or(false, |s| s.contains(ServiceFlags::NODE_NETWORK)) && n.start_height.map_or(false, |h| h >= tip)
+        });
+        if !suitable { return; }
+        { let mut p = self.tip_peers.lock(); if p.len() >= self.max_tip_peers || !p.insert(addr) { return; } }
+        debug!(parent: self.node().span(), "following the chain through {}", addr);
+        self.request_headers(addr);
+        if self.mempool.is_some() { let _ = self.unicast(addr, Message::MemPool); }
+    }
+
+    fn request_headers(&self, addr: SocketAddr) {
+        let locator = self.chain.read().locator();
+        *self.solicited.lock().entry(addr).or_default() += 1;
+        let _ = self.unicast(addr, Message::GetHeaders(locator));
+    }
+
+    // has to run before the hashes are added to the chain, blocks it already knows arent timed
+    fn observe_announcement(&self, src: SocketAddr, hashes: &[Hash], source: AnnounceSource) {
+        if hashes.is_empty() || !self.is_tip_peer(&src) { return; }
+        let now = Instant::now();
+        let peers = self.tip_peers.lock().len();
+        let delays: Vec<_> = {
+            let chain = self.chain.read();
+            let mut t = self.propagation.lock();
+            hashes.iter().filter_map(|h| t.observe(*h, src, source, now, !chain.contains(h), peers)).collect()
+        };
+        if delays.is_empty() { return; }
+        if let Some(n) = self.known_network.nodes.write().get_mut(&src) { for d in delays { n.announce_lag.add(d); } }
+    }
+
+    // the peer has at least the highest of these blocks the chain knows
+    fn note_announced(&self, src: SocketAddr, hashes: &[Hash]) {
+        let height = { let chain = self.chain.read(); hashes.iter().filter_map(|h| chain.height_of(h)).max() };
+        if let (Some(h), Some(n)) = (height, self.known_network.nodes.write().get_mut(&src)) { n.announced_height = Some(n.announced_height.map_or(h, |x| x.max(h))); }
+    }
+
+    pub fn is_tip_peer(&self, addr: &SocketAddr) -> bool { self.tip_peers.lock().contains(addr) }
+
+    // drops tip peers, observers and pending mempool requests of peers that went away and asks the remaining
+    // tip peers for headers, in case a block inv got missed
+    pub fn refresh_tip(&self) {
+        self.observers.lock().retain(|a| self.node().is_connected(*a));
+        if let Some(ref m) = self.mempool { m.lock().retain_pending(|a| self.node().is_connected(*a)); }
+        let peers: Vec<_> = { let mut p = self.tip_peers.lock(); p.retain(|a| self.node().is_connected(*a)); p.iter().copied().collect() };
+        self.solicited.lock().retain(|a, _| peers.contains(a));
+        for a in peers { self.request_headers(a); }
+    }
+
+    async fn probe_latency(&self, addr: SocketAddr) {
+        for _ in 0..PINGS_PER_CONNECTION {
+            if !self.node().is_connected(addr) { break; }
+            let nonce = Nonce::default();
+            self.pings.lock().insert(addr, (nonce, Instant::now()));
+            match self.unicast(addr, Message::Ping(nonce)) { Ok(rx) => { let _ = rx.await; } Err(_) => break }
+            sleep(Duration::from_secs(PING_INTERVAL_SECS)).await;
+        }
+        self.pings.lock().remove(&addr);
+    }
+
+    // disconnect after getting addrs (unless its just echoing our addr back)
+    async fn finish_addr_exchange(&self, src: SocketAddr, addrs: &[SocketAddr], num_overlay: usize) {
+        let n = addrs.len() + num_overlay;
+        if self.is_kept(&src) { return; }
+        if n > 1 || (n == 1 && addrs.first() != Some(&src)) {
+            self.node().disconnect(src).await;
+            self.known_network.set_node_state(src, ConnectionState::Disconnected);
+        }
+    }
+
+    // takes a connection slot for addr if its worth dialing and open plus reserved connections are under
+    // max_concurrent_connections. has to be called before spawning the connect, so a whole round of targets
+    // cant get past the cap before any of them started
+    pub fn reserve(&self, addr: SocketAddr) -> Option<Slot> {
+        if !self.known_network.nodes.read().contains_key(&addr) { return None; }
+        if self.node().is_connected(addr) || self.node().is_connecting(addr) { return None; }
+        let max = usize::from(self.settings.get().config.max_concurrent_connections);
+        let open = self.node().num_connected();
+        self.dialing.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |d| (open + d < max).then_some(d + 1)).ok()?;
+        Some(Slot(Arc::clone(&self.dialing)))
+    }
+
+    pub fn num_dialing(&self) -> usize { self.dialing.load(Ordering::SeqCst) }
+}
+
+// errors out of node.connect are either ours from perform_handshake or plain io ones
+fn failure_reason(e: &io::Error) -> HandshakeFailure {
+    if let Some(f) = e.get_ref().and_then(|x| x.downcast_ref::<HandshakeFailure>()) { return *f; }
+    match e.kind() {
+        io::ErrorKind::TimedOut => HandshakeFailure::Timeout,
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => HandshakeFailure::EarlyDisconnect,
+        _ => HandshakeFailure::Unreachable,
     }
 }
 
+fn fail(f: HandshakeFailure) -> io::Error {
+    let kind = match f { HandshakeFailure::Timeout => io::ErrorKind::TimedOut, HandshakeFailure::EarlyDisconnect => io::ErrorKind::UnexpectedEof, _ => io::ErrorKind::InvalidData };
+    io::Error::new(kind, f)
+}
+
+async fn send(stream: &mut TcpStream, codec: &mut MessageCodec, msg: Message) -> io::Result<()> {
+    let mut buf = BytesMut::new();
+    codec.encode(msg, &mut buf)?;
+    stream.write_all(&buf).await
+}
+
+// reads exactly one message, whatever the peer sends after it stays in the socket for the reading protocol
+async fn recv(stream: &mut TcpStream, codec: &mut MessageCodec) -> io::Result<Message> {
+    let mut buf = BytesMut::zeroed(HEADER_LEN);
+    stream.read_exact(&mut buf).await?;
+    let len = u32::from_le_bytes(buf[16..20].try_into().unwrap()) as usize;
+    if len > MAX_MESSAGE_LEN { return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes", len))); }
+    buf.resize(HEADER_LEN + len, 0);
+    stream.read_exact(&mut buf[HEADER_LEN..]).await?;
+    codec.decode(&mut buf)?.ok_or_else(|| io::ErrorKind::InvalidData.into())
+}
+
 #[async_trait::async_trait]
 impl Handshake for Crawler {
     const TIMEOUT_MS: u64 = 2000;
@@ -59,14 +230,59 @@ impl Handshake for Crawler {
     async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
         let addr = conn.addr();
         let listen: SocketAddr = ([127,0,0,1], 0).into();
-        let mut stream = Framed::new(self.borrow_stream(&mut conn), MessageCodec::default());
+        let stream = self.borrow_stream(&mut conn);
+        let mut codec = MessageCodec::new(self.network);
 
-        // pretend to be zcashd 5.4.2
-        let mut ver = Version::new(addr, listen);
-        ver.user_agent = VarStr("/MagicBean:5.4.2/".into());
-        ver.start_height = 3_150_000;
+        // configured user agent, by default pretending to be zcashd 5.4.2, and start height or our header tip
+        let mut ver = Version::new(self.network, addr, listen);
+        let settings = self.settings.get();
+        ver.user_agent = VarStr(settings.config.user_agent.clone());
+        ver.start_height = settings.config.start_height.unwrap_or_else(|| self.chain.read().tip().height);
         ver.relay = true;
-        stream.send(Message::Version(ver)).await?;
+        let ts = Instant::now();
+        send(stream, &mut codec, Message::Version(ver)).await?;
+        // zip-155: has to go between version and verack so the peer gossips addrv2 to us
+        send(stream, &mut codec, Message::SendAddrV2).await?;
+
+        // wait for their version and verack, in any order, answering their version with our verack
+        let mut version = None;
+        let mut verack = false;
+        let mut received = 0;
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(HANDSHAKE_REPLY_TIMEOUT_MS);
+        while version.is_none() || !verack {
+            let msg = match tokio::time::timeout_at(deadline, recv(stream, &mut codec)).await {
+                Err(_) => return Err(fail(HandshakeFailure::Timeout)),
+                Ok(Err(e)) if e.get_ref().map_or(false, |x| x.is::<WrongMagic>()) => return Err(fail(HandshakeFailure::WrongMagic)),
+                Ok(Err(e)) if matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset) => return Err(fail(HandshakeFailure::EarlyDisconnect)),
+                Ok(Err(_)) => return Err(fail(HandshakeFailure::ProtocolError)),
+                Ok(Ok(m)) => m,
+            };
+            match msg {
+                Message::Version(v) if version.is_none() => { received = unix_now() as i64; send(stream, &mut codec, Message::Verack).await?; version = Some(v); }
+                Message::Verack => verack = true,
+                Message::Reject(r) => {
+                    debug!(parent: self.node().span(), "{} rejected our {}: {}", addr, r.message.0, r.reason.0);
+                    return Err(fail(HandshakeFailure::Reject));
+                }
+                _ => {}
+            }
+        }
+
+        let rtt = ts.elapsed();
+        self.counters.observe_handshake(rtt);
+        if let (Some(v), Some(n)) = (version, self.known_network.nodes.write().get_mut(&addr)) {
+            info!(parent: self.node().span(), "version from {}", addr);
+            n.protocol_version = Some(v.version);
+            n.parsed_user_agent = Some(UserAgent::parse(&v.user_agent.0));
+            n.user_agent = Some(v.user_agent);
+            n.services = Some(v.services);
+            n.start_height = Some(v.start_height);
+            n.remote_timestamp = Some(v.timestamp.unix_timestamp());
+            n.clock_offset = Some(v.timestamp.unix_timestamp() - received);
+            n.remote_nonce = Some(v.nonce.value());
+            n.remote_addr_from = Some(v.addr_from.addr);
+            n.handshake_time = Some(rtt);
+        }
         Ok(conn)
     }
 }
@@ -75,36 +291,77 @@ impl Handshake for Crawler {
 impl Reading for Crawler {
     type Message = Message;
     type Codec = MessageCodec;
-    fn codec(&self, _: SocketAddr, _: ConnectionSide) -> Self::Codec { Default::default() }
+    fn codec(&self, _: SocketAddr, _: ConnectionSide) -> Self::Codec { MessageCodec::new(self.network) }
 
     async fn process_message(&self, src: SocketAddr, msg: Self::Message) -> io::Result<()> {
         match msg {
             Message::Addr(a) => {
-                let n = a.addrs.len();
-                info!(parent: self.node().span(), "got {} addrs from {}", n, src);
+                self.counters.addr_messages.fetch_add(1, Ordering::Relaxed);
+                self.counters.addrs_received.fetch_add(a.addrs.len() as u64, Ordering::Relaxed);
+                info!(parent: self.node().span(), "got {} addrs from {}", a.addrs.len(), src);
                 let addrs: Vec<_> = a.addrs.iter().map(|x| x.addr).collect();
                 self.known_network.add_addrs(src, &addrs);
-                // disconnect after getting addrs (unless its just echoing our addr back)
-                if n > 1 || (n == 1 && a.addrs[0].addr != src) {
-                    self.node().disconnect(src).await;
-                    self.known_network.set_node_state(src, ConnectionState::Disconnected);
-                }
+                self.known_network.add_gossip_services(&a.addrs.iter().map(|x| (x.addr, x.services)).collect::<Vec<_>>());
+                self.finish_addr_exchange(src, &addrs, 0).await;
+            }
+            Message::AddrV2(a) => {
+                self.counters.addr_messages.fetch_add(1, Ordering::Relaxed);
+                self.counters.addrs_received.fetch_add(a.addrs.len() as u64, Ordering::Relaxed);
+                info!(parent: self.node().span(), "got {} addrv2 from {}", a.addrs.len(), src);
+                let addrs: Vec<_> = a.addrs.iter().filter_map(|x| x.socket_addr()).collect();
+                let overlay: Vec<_> = a.addrs.iter().filter(|x| x.socket_addr().is_none()).map(|x| x.to_string()).collect();
+                self.known_network.add_addrs(src, &addrs);
+                self.known_network.add_gossip_services(&a.addrs.iter().filter_map(|x| Some((x.socket_addr()?, x.services))).collect::<Vec<_>>());
+                self.known_network.add_overlay_addrs(&overlay);
+                self.finish_addr_exchange(src, &addrs, overlay.len()).await;
             }
             Message::Ping(nonce) => { let _ = self.unicast(src, Message::Pong(nonce))?.await; }
+            Message::Pong(nonce) => {
+                let sent = { let mut p = self.pings.lock(); match p.get(&src) { Some((n, t)) if *n == nonce => { let t = *t; p.remove(&src); Some(t) } _ => None } };
+                if let Some(t) = sent { if let Some(n) = self.known_network.nodes.write().get_mut(&src) { n.latency.add(t.elapsed()); } }
+            }
+            Message::Inv(inv) => {
+                let blocks: Vec<_> = inv.inventory.iter().filter_map(|i| match i { InvHash::Block(h) => Some(*h), _ => None }).collect();
+                self.observe_announcement(src, &blocks, AnnounceSource::Inv);
+                self.note_announced(src, &blocks);
+                let unknown = { let chain = self.chain.read(); blocks.iter().any(|h| !chain.contains(h)) };
+                if unknown && self.is_tip_peer(&src) { self.request_headers(src); }
+                if let Some(ref m) = self.mempool {
+                    let fetch = m.lock().observe(src, &inv.inventory, Instant::now());
+                    if !fetch.is_empty() { let _ = self.unicast(src, Message::GetData(Inv::new(fetch))); }
+                }
+            }
+            Message::Tx(tx) => { if let Some(ref m) = self.mempool { m.lock().record_tx(src, &tx); } }
+            Message::NotFound(inv) => { if let Some(ref m) = self.mempool { m.lock().not_found(src, &inv.inventory); } }
+            // only tip peers are asked for headers and followed, anyone else could feed us a made up chain
+            Message::Headers(h) if self.is_tip_peer(&src) => {
+                let hashes: Vec<_> = h.headers.iter().filter_map(|x| x.double_sha256().ok()).collect();
+                // once live, a lone new header on top of our tip is an announcement even with a getheaders
+                // outstanding, the reply comes after it. while catching up every headers is taken as a reply
+                let announcement = {
+                    let chain = self.chain.read();
+                    chain.live_height().is_some() && h.headers.len() == 1 && h.headers[0].prev_block == chain.tip().hash && hashes.first().map_or(false, |x| !chain.contains(x))
+                };
+                let reply = !announcement && { let mut s = self.solicited.lock(); match s.get_mut(&src) { Some(n) if *n > 0 => { *n -= 1; true } _ => false } };
+                if !reply { self.observe_announcement(src, &hashes, AnnounceSource::Headers); }
+                let peer_height = self.known_network.nodes.read().get(&src).and_then(|n| n.start_height).unwrap_or(i32::MAX);
+                let (added, tip) = { let mut chain = self.chain.write(); (if reply { chain.add_reply(&h.headers, peer_height) } else { chain.add_headers(&h.headers).0 }, chain.tip()) };
+                if added > 0 { info!(parent: self.node().span(), "{} new headers from {}, tip {}", added, src, tip); }
+                self.note_announced(src, &hashes);
+                // a full reply means theres more to fetch
+                if added > 0 && h.headers.len() >= MAX_HEADERS_PER_MSG { self.request_headers(src); }
+            }
+            // some peers push new blocks straight away instead of announcing them
+            Message::Block(b) if self.is_tip_peer(&src) => {
+                if let Ok(hash) = b.header.double_sha256() {
+                    self.observe_announcement(src, &[hash], AnnounceSource::Block);
+                    let connected = { let mut chain = self.chain.write(); chain.add_headers(std::slice::from_ref(&b.header)); chain.contains(&hash) };
+                    if connected { self.note_announced(src, &[hash]); } else { self.request_headers(src); }
+                }
+            }
             Message::GetAddr => { let _ = self.unicast(src, Message::Addr(Addr::empty()))?.await; }
             Message::GetHeaders(_) => { let _ = self.unicast(src, Message::Headers(Headers::empty()))?.await; }
             Message::GetData(inv) => { let _ = self.unicast(src, Message::NotFound(inv.clone()))?.await; }
-            Message::Version(v) => {
-                info!(parent: self.node().span(), "version from {}", src);
-                if let Some(n) = self.known_network.nodes.write().get_mut(&src) {
-                    n.protocol_version = Some(v.version);
-                    n.user_agent = Some(v.user_agent);
-                    n.services = Some(v.services);
-                    n.start_height = Some(v.start_height);
-                }
-                let _ = self.unicast(src, Message::Verack)?.await;
-                let _ = self.unicast(src, Message::GetAddr)?.await;
-            }
             _ => {}
         }
         Ok(())
@@ -114,5 +371,5 @@ impl Reading for Crawler {
 impl Writing for Crawler {
     type Message = Message;
     type Codec = MessageCodec;
-    fn codec(&self, _: SocketAddr, _: ConnectionSide) -> Self::Codec { Default::default() }
+    fn codec(&self, _: SocketAddr, _: ConnectionSide) -> Self::Codec { MessageCodec::new(self.network) }
 }
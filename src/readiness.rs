@@ -0,0 +1,173 @@
+// network upgrade readiness - share of relevant nodes that will follow an upcoming upgrade
+// upgrades come from a toml file, see upgrades.toml for the bundled list
+
+use std::{collections::HashMap, fs, net::SocketAddr, path::Path};
+use serde::{Deserialize, Serialize};
+use ziggurat_zcash::protocol::network::Network;
+use crate::{classifier::NodeClassifier, network::{KnownNode, UptimeWindow}, user_agent::Version};
+
+pub const DEFAULT_UPGRADES: &str = include_str!("../upgrades.toml");
+// uptime weights come from this window, a node that is mostly down matters less on activation day
+const WEIGHT_WINDOW: UptimeWindow = UptimeWindow::D7;
+
+#[derive(Clone, Deserialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub network: String,
+    pub activation_height: i32,
+    pub min_protocol_version: u32,
+    #[serde(default)]
+    pub min_zcashd_version: Option<String>,
+    #[serde(default)]
+    pub min_zebrad_version: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct UpgradeFile { #[serde(rename = "upgrade")] upgrades: Vec<Upgrade> }
+
+#[derive(Clone, Serialize)]
+pub struct Laggard {
+    pub addr: SocketAddr,
+    pub client: String,
+    pub user_agent: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct UpgradeReadiness {
+    pub name: String,
+    pub activation_height: i32,
+    pub tip_height: i32,
+    // negative once the upgrade is active
+    pub blocks_until_activation: i32,
+    pub relevant_nodes: usize,
+    pub ready_nodes: usize,
+    pub ready_pct: f64,
+    // same, with every node weighted by its 7 day uptime
+    pub ready_pct_uptime_weighted: f64,
+    pub by_client: HashMap<String, ClientReadiness>,
+    pub laggards: Vec<Laggard>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct ClientReadiness { pub nodes: usize, pub ready: usize }
+
+pub struct Readiness { upgrades: Vec<(Upgrade, Option<Version>, Option<Version>)> }
+
+impl Readiness {
+    // keeps the upgrades of the given network
+    pub fn new(upgrades: Vec<Upgrade>, network: Network) -> Result<Self, String> {
+        let mut out = Vec::new();
+        for u in upgrades {
+            let net: Network = u.network.parse().map_err(|e| format!("upgrade {}: {}", u.name, e))?;
+            if net != network { continue; }
+            let ver = |v: &Option<String>| v.as_deref().map(|s| Version::parse(s).ok_or_else(|| format!("upgrade {}: bad version {:?}", u.name, s))).transpose();
+            let (zcashd, zebrad) = (ver(&u.min_zcashd_version)?, ver(&u.min_zebrad_version)?);
+            out.push((u, zcashd, zebrad));
+        }
+        Ok(Self { upgrades: out })
+    }
+
+    pub fn parse(s: &str, network: Network) -> Result<Self, String> {
+        Self::new(toml::from_str::<UpgradeFile>(s).map_err(|e| e.to_string())?.upgrades, network)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, network: Network) -> Result<Self, String> {
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?, network)
+    }
+
+    pub fn names(&self) -> Vec<String> { self.upgrades.iter().map(|(u, _, _)| u.name.clone()).collect() }
+
+    // only the named upgrade if given, otherwise all of them
+    pub fn report(&self, nodes: &HashMap<SocketAddr, KnownNode>, cl: &NodeClassifier, name: Option<&str>) -> Vec<UpgradeReadiness> {
+        let tip = cl.tip(nodes);
+        self.upgrades.iter().filter(|(u, _, _)| name.map_or(true, |n| u.name.eq_ignore_ascii_case(n))).map(|(u, zcashd, zebrad)| {
+            let mut r = UpgradeReadiness {
+                name: u.name.clone(), activation_height: u.activation_height, tip_height: tip, blocks_until_activation: u.activation_height - tip,
+                relevant_nodes: 0, ready_nodes: 0, ready_pct: 0.0, ready_pct_uptime_weighted: 0.0, by_client: HashMap::new(), laggards: Vec::new(),
+            };
+            let (mut weight, mut ready_weight) = (0.0, 0.0);
+            for (addr, n) in nodes {
+                let c = cl.classify(addr, n, tip, None);
+                if !c.is_relevant() { continue; }
+                let mut reasons = Vec::new();
+                match n.protocol_version.map(|v| v.0) {
+                    Some(v) if v >= u.min_protocol_version => {}
+                    v => reasons.push(format!("protocol version {} below {}", v.map_or("unknown".into(), |v| v.to_string()), u.min_protocol_version)),
+                }
+                let min = match c.client.as_str() { "zcashd" => zcashd.as_ref(), "zebra" => zebrad.as_ref(), _ => None };
+                if let Some(min) = min {
+                    match n.parsed_user_agent.as_ref().and_then(|ua| ua.implementation()).and_then(|i| i.version.as_ref()) {
+                        Some(v) if v >= min => {}
+                        v => reasons.push(format!("{} version {} below {}", c.client, v.map_or("unknown".into(), |v| v.to_string()), min)),
+                    }
+                }
+                let ready = reasons.is_empty();
+                let w = n.reachability.score(WEIGHT_WINDOW);
+                r.relevant_nodes += 1;
+                weight += w;
+                let e = r.by_client.entry(c.client.clone()).or_default();
+                e.nodes += 1;
+                if ready {
+                    r.ready_nodes += 1; e.ready += 1; ready_weight += w;
+                } else {
+                    r.laggards.push(Laggard { addr: *addr, client: c.client, user_agent: n.user_agent.as_ref().map(|x| x.0.clone()), protocol_version: n.protocol_version.map(|v| v.0), reasons });
+                }
+            }
+            if r.relevant_nodes > 0 { r.ready_pct = 100.0 * r.ready_nodes as f64 / r.relevant_nodes as f64; }
+            if weight > 0.0 { r.ready_pct_uptime_weighted = 100.0 * ready_weight / weight; }
+            r.laggards.sort_by_key(|l| l.addr);
+            r
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{classifier::Rules, user_agent::UserAgent};
+    use ziggurat_zcash::protocol::payload::{ProtocolVersion, VarStr};
+
+    fn node(ua: &str, proto: u32) -> KnownNode {
+        KnownNode { user_agent: Some(VarStr(ua.into())), parsed_user_agent: Some(UserAgent::parse(ua)), protocol_version: Some(ProtocolVersion(proto)), start_height: Some(2_700_000), ..Default::default() }
+    }
+
+    #[test]
+    fn counts_ready_nodes_and_laggards() {
+        let r = Readiness::parse(DEFAULT_UPGRADES, Network::Mainnet).unwrap();
+        assert_eq!(r.names(), vec!["NU6".to_string(), "NU6.1".to_string()]);
+        let cl = NodeClassifier::new(Rules::default(), Network::Mainnet).unwrap();
+        let mut nodes = HashMap::new();
+        nodes.insert("1.1.1.1:8233".parse().unwrap(), node("/Zebra:2.0.1/", 170_120));
+        nodes.insert("2.2.2.2:8233".parse().unwrap(), node("/Zebra:1.9.0/", 170_120));
+        nodes.insert("3.3.3.3:8233".parse().unwrap(), node("/Zebra:2.1.0/", 170_100));
+
+        let out = r.report(&nodes, &cl, Some("nu6"));
+        assert_eq!((out[0].relevant_nodes, out[0].ready_nodes), (3, 1));
+        assert_eq!(out[0].laggards.len(), 2);
+        assert!(out[0].laggards[0].reasons[0].contains("zebra version 1.9.0"));
+        assert!(out[0].laggards[1].reasons[0].contains("protocol version 170100"));
+
+        // nu6.1 only looks at the protocol version
+        nodes.insert("4.4.4.4:8233".parse().unwrap(), node("/Zebra:2.1.0/", 170_140));
+        let out = r.report(&nodes, &cl, Some("nu6.1"));
+        assert_eq!((out[0].name.as_str(), out[0].activation_height), ("NU6.1", 3_146_400));
+        assert_eq!((out[0].relevant_nodes, out[0].ready_nodes), (4, 1));
+    }
+
+    #[test]
+    fn zcashd_6_is_ready() {
+        let r = Readiness::parse(DEFAULT_UPGRADES, Network::Mainnet).unwrap();
+        let cl = NodeClassifier::new(Rules::default(), Network::Mainnet).unwrap();
+        let mut nodes = HashMap::new();
+        nodes.insert("1.1.1.1:8233".parse().unwrap(), node("/MagicBean:6.0.0/", 170_120));
+        nodes.insert("2.2.2.2:8233".parse().unwrap(), node("/MagicBean:5.10.0/", 170_120));
+
+        let out = r.report(&nodes, &cl, Some("nu6"));
+        assert_eq!((out[0].relevant_nodes, out[0].ready_nodes), (2, 1));
+        assert_eq!((out[0].by_client["zcashd"].nodes, out[0].by_client["zcashd"].ready), (2, 1));
+        assert_eq!(out[0].laggards[0].addr, "2.2.2.2:8233".parse().unwrap());
+        assert!(out[0].laggards[0].reasons[0].contains("zcashd version 5.10.0"));
+    }
+}
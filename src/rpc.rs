@@ -1,24 +1,37 @@
 // rpc server - json-rpc api for getting node info
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use jsonrpsee::server::{RpcModule, ServerBuilder, ServerHandle};
-use parking_lot::Mutex;
-use serde::Serialize;
+use std::{collections::{BTreeMap, HashMap, HashSet}, net::SocketAddr, sync::Arc};
+use jsonrpsee::{core::Error, server::{RpcModule, ServerBuilder, ServerHandle}, types::Params};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::debug;
-use ziggurat_core_crawler::summary::NetworkSummary;
-use crate::network::KnownNode;
+use tracing::{debug, error, info};
+use ziggurat_core_crawler::{connection::KnownConnection, summary::NetworkSummary};
+use ziggurat_zcash::protocol::payload::ServiceFlags;
+use crate::{aliases::Aliases, chain::HeaderChain, classifier::{NodeClassifier, VerdictCounts}, config::SharedSettings, export::{export_graph, GraphFormat}, geo::{GeoDb, GeoInfo}, graph::GraphMetrics, history::StatsHistory, mempool::MempoolTracker, network::{FailureCounts, HandshakeFailure, KnownNode, LatencySummary, UptimeScores, UptimeWindow}, propagation::PropagationTracker, readiness::Readiness, user_agent::version_histograms};
 
-const HEIGHT_TOLERANCE: i32 = 20000;
-const MIN_HEIGHT: i32 = 2_500_000;
 pub const MAX_RESPONSE_SIZE: u32 = 200_000_000;
 
 #[derive(Clone, Serialize)]
 pub struct NodeInfo {
     pub ip: String, pub port: u16,
     pub protocol_version: Option<u32>, pub user_agent: Option<String>,
-    pub height: Option<i32>, pub services: Option<u64>,
+    pub height: Option<i32>, pub services: Option<u64>, pub service_flags: Vec<String>, pub gossip_services: Option<u64>,
     pub last_seen_secs: u64, pub is_relevant: bool, pub is_flux: bool, pub client_type: String,
+    pub uptime: UptimeScores,
+    pub handshake_ms: Option<u64>,
+    pub clock_offset_secs: Option<i64>,
+    // blocks the last one it announced is behind the header chain tip, tip peers only
+    pub lag_blocks: Option<i32>,
+    // other addrs the same node was reached at
+    pub aliases: Vec<SocketAddr>,
+    pub latency: Option<LatencySummary>,
+    pub handshake_failures: FailureCounts,
+    pub last_failure: Option<HandshakeFailure>,
+    // why the node isnt relevant, None if it is
+    pub filter_reason: Option<String>,
+    #[serde(flatten)]
+    pub geo: GeoInfo,
 }
 
 #[derive(Clone, Serialize)]
@@ -26,6 +39,8 @@ pub struct NodeGeo {
     pub ip: String,
     pub client_type: String,
     pub height: Option<i32>,
+    #[serde(flatten)]
+    pub geo: GeoInfo,
 }
 
 #[derive(Clone, Serialize)]
@@ -39,68 +54,189 @@ pub struct DiagnosticInfo {
     pub passed_filters: usize,
     pub zcashd_nodes: usize,
     pub zebra_nodes: usize,
+    pub filtered_by_port: usize,
+    pub filtered_by_unknown_client: usize,
+    pub tip_height_estimate: i32,
+    #[serde(flatten)]
+    pub counts: VerdictCounts,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Stats {
     pub num_known_nodes: usize, pub num_contacted_nodes: usize, pub num_relevant_zcash_nodes: usize,
     pub num_zcashd_nodes: usize, pub num_zebra_nodes: usize, pub num_flux_nodes: usize, pub num_other_nodes: usize,
     pub tip_height_estimate: i32, pub crawler_runtime_secs: u64,
+    // the counts above are per address, these merge addrs that belong to the same node
+    #[serde(default)]
+    pub num_unique_contacted_nodes: usize,
+    #[serde(default)]
+    pub num_unique_relevant_nodes: usize,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ServiceCombo { pub services: u64, pub flags: Vec<String>, pub count: usize }
+
+// nodes whose own version services differ from what peers gossip about them, per flag
+#[derive(Clone, Default, Serialize)]
+pub struct GossipComparison {
+    pub compared: usize,
+    pub matching: usize,
+    pub only_in_version: BTreeMap<String, usize>,
+    pub only_in_gossip: BTreeMap<String, usize>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct ServiceStats {
+    pub combinations: Vec<ServiceCombo>,
+    pub by_flag: BTreeMap<String, usize>,
+    pub gossip_vs_version: GossipComparison,
+}
+
+// upper bounds on |offset| in secs, 70 min is as far as bitcoin-derived nodes let peers pull their adjusted time
+const SKEW_BUCKETS: [(i64, &str); 5] = [(10, "<=10s"), (60, "<=1m"), (300, "<=5m"), (4200, "<=70m"), (i64::MAX, ">70m")];
+const DEFAULT_SKEW_OUTLIER_SECS: i64 = 600;
+
+#[derive(Clone, Serialize)]
+pub struct SkewedNode { pub addr: SocketAddr, pub offset_secs: i64, pub user_agent: Option<String> }
+
+#[derive(Clone, Default, Serialize)]
+pub struct ClockSkewStats {
+    pub num_measured: usize,
+    pub mean_secs: f64,
+    pub median_secs: i64,
+    pub p5_secs: i64,
+    pub p95_secs: i64,
+    pub min_secs: i64,
+    pub max_secs: i64,
+    pub buckets: BTreeMap<&'static str, usize>,
+    pub outlier_threshold_secs: i64,
+    pub outliers: Vec<SkewedNode>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct LatencyGroup { pub group: String, pub num_nodes: usize, pub median_ms: u32, pub p90_ms: u32, pub min_ms: u32 }
+
+#[derive(Clone, Serialize)]
+pub struct AnnounceLag {
+    pub addr: SocketAddr,
+    pub user_agent: Option<String>,
+    // of the last LATENCY_SAMPLES announcements
+    pub blocks: usize,
+    #[serde(flatten)]
+    pub lag: LatencySummary,
 }
 
 #[derive(Clone, Serialize)]
 pub struct NodesResponse { pub stats: Stats, pub nodes: Vec<NodeInfo> }
 
+// handles into the crawler state the methods read from
 pub struct RpcContext {
-    summary: Arc<Mutex<NetworkSummary>>,
-    nodes: Arc<Mutex<HashMap<SocketAddr, KnownNode>>>,
+    pub summary: Arc<Mutex<NetworkSummary>>,
+    pub nodes: Arc<Mutex<HashMap<SocketAddr, KnownNode>>>,
+    pub conns: Arc<Mutex<HashSet<KnownConnection>>>,
+    pub history: Arc<Mutex<StatsHistory>>,
+    pub settings: SharedSettings,
+    pub geo: Arc<GeoDb>,
+    pub chain: Arc<RwLock<HeaderChain>>,
+    pub propagation: Arc<Mutex<PropagationTracker>>,
+    pub mempool: Option<Arc<Mutex<MempoolTracker>>>,
+    pub readiness: Arc<Readiness>,
+    pub graph: Arc<Mutex<GraphMetrics>>,
 }
 
 impl RpcContext {
-    pub fn new(s: Arc<Mutex<NetworkSummary>>, n: Arc<Mutex<HashMap<SocketAddr, KnownNode>>>) -> Self {
-        Self { summary: s, nodes: n }
+    // one snapshot per call, a reload during a request doesnt change the rules halfway
+    fn classifier(&self) -> Arc<NodeClassifier> { Arc::clone(&self.settings.get().classifier) }
+
+    fn check_access(&self, method: &str) -> Result<(), Error> {
+        if self.settings.get().config.rpc.disabled_methods.iter().any(|m| m == method) { return Err(Error::Custom(format!("{} is disabled", method))); }
+        Ok(())
     }
 }
 
-fn get_tip(nodes: &HashMap<SocketAddr, KnownNode>) -> i32 {
-    let mut h: Vec<_> = nodes.values().filter_map(|n| n.start_height).filter(|x| *x > MIN_HEIGHT).collect();
-    if h.is_empty() { return MIN_HEIGHT; }
-    h.sort();
-    h.get((h.len() as f64 * 0.95) as usize).copied().unwrap_or(MIN_HEIGHT)
+pub fn compute_stats(nodes: &HashMap<SocketAddr, KnownNode>, cl: &NodeClassifier, runtime_secs: u64) -> Stats {
+    let tip = cl.tip(nodes);
+    let mut stats = Stats { num_known_nodes: nodes.len(), num_contacted_nodes: 0, num_relevant_zcash_nodes: 0,
+        num_zcashd_nodes: 0, num_zebra_nodes: 0, num_flux_nodes: 0, num_other_nodes: 0, tip_height_estimate: tip, crawler_runtime_secs: runtime_secs,
+        num_unique_contacted_nodes: 0, num_unique_relevant_nodes: 0 };
+    let aliases = Aliases::find(nodes);
+    let mut contacted = Vec::new();
+    let mut relevant = Vec::new();
+
+    for (a, n) in nodes.iter() {
+        if n.user_agent.is_none() { continue; }
+        stats.num_contacted_nodes += 1;
+        contacted.push(a);
+        let c = cl.classify(a, n, tip, None);
+        if c.is_relevant() { relevant.push(a); }
+        if c.fork.is_some() { stats.num_flux_nodes += 1; }
+        else { match c.client.as_str() { "zcashd" => stats.num_zcashd_nodes += 1, "zebra" => stats.num_zebra_nodes += 1, _ => stats.num_other_nodes += 1 } }
+        if c.is_relevant() { stats.num_relevant_zcash_nodes += 1; }
+    }
+    stats.num_unique_contacted_nodes = aliases.count_unique(contacted);
+    stats.num_unique_relevant_nodes = aliases.count_unique(relevant);
+    stats
 }
 
-fn client_type(ua: &str) -> (String, bool) {
-    let lower = ua.to_lowercase();
-    if lower.contains("flux") { return ("flux".into(), true); }
-    if lower.contains("magicbean") {
-        if let Some(i) = lower.find("magicbean:") {
-            let ver = &lower[i+10..];
-            if let Some(d) = ver.find('.') {
-                if let Ok(maj) = ver[..d].parse::<u32>() { if maj >= 6 { return ("flux".into(), true); } }
-            }
-        }
-        return ("zcashd".into(), false);
+pub fn service_stats<'a, I: IntoIterator<Item = &'a KnownNode>>(nodes: I) -> ServiceStats {
+    let mut out = ServiceStats::default();
+    let mut combos: HashMap<ServiceFlags, usize> = HashMap::new();
+    for n in nodes {
+        let s = match n.services { Some(s) => s, None => continue };
+        *combos.entry(s).or_default() += 1;
+        for f in s.names() { *out.by_flag.entry(f).or_default() += 1; }
+
+        let g = match n.gossip_services { Some(g) => g, None => continue };
+        let cmp = &mut out.gossip_vs_version;
+        cmp.compared += 1;
+        if g == s { cmp.matching += 1; continue; }
+        for f in s.difference(g).names() { *cmp.only_in_version.entry(f).or_default() += 1; }
+        for f in g.difference(s).names() { *cmp.only_in_gossip.entry(f).or_default() += 1; }
     }
-    if lower.contains("zebra") { return ("zebra".into(), false); }
-    ("other".into(), false)
+    out.combinations = combos.into_iter().map(|(s, count)| ServiceCombo { services: s.bits(), flags: s.names(), count }).collect();
+    out.combinations.sort_by(|a, b| b.count.cmp(&a.count).then(a.services.cmp(&b.services)));
+    out
 }
 
-fn is_good_node(n: &KnownNode, tip: i32) -> bool {
-    let ua = match &n.user_agent { Some(x) => x.0.to_lowercase(), None => return false };
-    if !ua.starts_with("/magicbean") && !ua.starts_with("/zebra") { return false; }
-    if ua.contains("flux") { return false; }
-    if ua.contains("magicbean") {
-        if let Some(i) = ua.find("magicbean:") {
-            let ver = &ua[i+10..];
-            if let Some(d) = ver.find('.') {
-                if let Ok(maj) = ver[..d].parse::<u32>() { if maj >= 6 { return false; } }
-            }
-        }
+pub fn clock_skew_stats(nodes: &HashMap<SocketAddr, KnownNode>, outlier_secs: i64) -> ClockSkewStats {
+    let mut offsets: Vec<i64> = nodes.values().filter_map(|n| n.clock_offset).collect();
+    let mut out = ClockSkewStats { outlier_threshold_secs: outlier_secs, ..Default::default() };
+    for (_, name) in SKEW_BUCKETS { out.buckets.insert(name, 0); }
+    if offsets.is_empty() { return out; }
+    offsets.sort();
+    let pct = |p: f64| offsets[((offsets.len() - 1) as f64 * p).round() as usize];
+    out.num_measured = offsets.len();
+    out.mean_secs = offsets.iter().sum::<i64>() as f64 / offsets.len() as f64;
+    out.median_secs = pct(0.5);
+    out.p5_secs = pct(0.05);
+    out.p95_secs = pct(0.95);
+    out.min_secs = offsets[0];
+    out.max_secs = offsets[offsets.len() - 1];
+    for o in &offsets {
+        if let Some((_, name)) = SKEW_BUCKETS.iter().find(|(max, _)| o.abs() <= *max) { *out.buckets.entry(name).or_default() += 1; }
+    }
+    out.outliers = nodes.iter().filter_map(|(a, n)| n.clock_offset.filter(|o| o.abs() > outlier_secs)
+        .map(|o| SkewedNode { addr: *a, offset_secs: o, user_agent: n.user_agent.as_ref().map(|x| x.0.clone()) })).collect();
+    out.outliers.sort_by(|a, b| b.offset_secs.abs().cmp(&a.offset_secs.abs()));
+    out
+}
+
+// median ping of each node, grouped by country or asn; nodes without a geo match end up in "unknown"
+pub fn latency_by_location(nodes: &HashMap<SocketAddr, KnownNode>, geo: &GeoDb, by_asn: bool) -> Vec<LatencyGroup> {
+    let mut groups: HashMap<String, Vec<u32>> = HashMap::new();
+    for (a, n) in nodes.iter() {
+        let med = match n.latency.median_ms() { Some(m) => m, None => continue };
+        let g = geo.lookup(a.ip());
+        let key = if by_asn { g.asn.map(|x| format!("AS{} {}", x, g.org.unwrap_or_default()).trim().to_string()) } else { g.country_code };
+        groups.entry(key.unwrap_or_else(|| "unknown".into())).or_default().push(med);
     }
-    let h = n.start_height.unwrap_or(0);
-    if h < MIN_HEIGHT { return false; }
-    if !ua.contains("magicbean") && (h - tip).abs() > HEIGHT_TOLERANCE { return false; }
-    true
+    let mut out: Vec<_> = groups.into_iter().map(|(group, mut ms)| {
+        ms.sort_unstable();
+        let pct = |p: f64| ms[((ms.len() - 1) as f64 * p).round() as usize];
+        LatencyGroup { num_nodes: ms.len(), median_ms: pct(0.5), p90_ms: pct(0.9), min_ms: ms[0], group }
+    }).collect();
+    out.sort_by(|a, b| b.num_nodes.cmp(&a.num_nodes).then(a.group.cmp(&b.group)));
+    out
 }
 
 pub async fn initialize_rpc_server(addr: SocketAddr, ctx: RpcContext) -> ServerHandle {
@@ -112,162 +248,211 @@ pub async fn initialize_rpc_server(addr: SocketAddr, ctx: RpcContext) -> ServerH
     srv.start(module).unwrap()
 }
 
+// compares every byte so the time taken doesnt tell how much of the token was right
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// every method is checked against the rpc access rules of the current settings first
+fn register<R, F>(m: &mut RpcModule<RpcContext>, name: &'static str, f: F)
+where R: Serialize + 'static, F: Fn(Params, &RpcContext) -> Result<R, Error> + Send + Sync + 'static {
+    m.register_method(name, move |p, c| { c.check_access(name)?; f(p, c) }).unwrap();
+}
+
 fn make_module(ctx: RpcContext) -> RpcModule<RpcContext> {
     let mut m = RpcModule::new(ctx);
 
-    m.register_method("getmetrics", |_, c| Ok(c.summary.lock().clone())).unwrap();
+    register(&mut m, "getmetrics", |_, c| Ok(c.summary.lock().clone()));
 
-    m.register_method("getstats", |_, c| {
-        let nodes = c.nodes.lock();
-        let tip = get_tip(&nodes);
-        let mut contacted = 0; let mut relevant = 0;
-        let mut zd = 0; let mut zb = 0; let mut fx = 0; let mut ot = 0;
+    register(&mut m, "getstats", |_, c| {
+        let classifier = c.classifier();
+        let rt = c.summary.lock().crawler_runtime.as_secs();
+        Ok(compute_stats(&c.nodes.lock(), &classifier, rt))
+    });
 
-        for (_, n) in nodes.iter() {
-            if n.user_agent.is_none() { continue; }
-            contacted += 1;
-            let ua = n.user_agent.as_ref().map(|x| x.0.clone()).unwrap_or_default();
-            let (t, _) = client_type(&ua);
-            match t.as_str() { "zcashd" => zd += 1, "zebra" => zb += 1, "flux" => fx += 1, _ => ot += 1 }
-            if is_good_node(n, tip) { relevant += 1; }
+    // height and hash of the best header we know, synced is false while still catching up
+    register(&mut m, "gettip", |_, c| Ok(c.chain.read().info()));
+
+    // params: [count], propagation curves of the last blocks, newest first
+    register(&mut m, "getpropagation", |p, c| {
+        let count: Option<usize> = p.sequence().optional_next().unwrap_or(None);
+        // chain before tracker, the order observe_announcement takes them in
+        let chain = c.chain.read();
+        Ok(c.propagation.lock().report(&chain, count.unwrap_or(20)))
+    });
+
+    // how far behind the first announcer each tip peer announces blocks, fastest first
+    register(&mut m, "getannouncelag", |_, c| {
+        let mut out: Vec<AnnounceLag> = c.nodes.lock().iter().filter_map(|(a, n)| Some(AnnounceLag {
+            addr: *a, user_agent: n.user_agent.as_ref().map(|x| x.0.clone()), blocks: n.announce_lag.samples.len(), lag: n.announce_lag.summary()?,
+        })).collect();
+        out.sort_by_key(|x| x.lag.median_ms);
+        Ok(out)
+    });
+
+    // tx relay per peer and per tx version, null unless running with --observe-mempool
+    register(&mut m, "getrelaystats", |_, c| Ok(c.mempool.as_ref().map(|m| m.lock().stats())));
+
+    // params: [upgrade name], share of relevant nodes ready for each configured upgrade and who isnt
+    register(&mut m, "getupgradereadiness", |p, c| {
+        let classifier = c.classifier();
+        let name: Option<String> = p.sequence().optional_next().unwrap_or(None);
+        Ok(c.readiness.report(&c.nodes.lock(), &classifier, name.as_deref()))
+    });
+
+    // degree distribution, centrality, clustering, diameter and components of the good-node graph, as of the last summary
+    register(&mut m, "getgraphmetrics", |_, c| Ok(c.graph.lock().clone()));
+
+    // params: [format "graphml" (default), "gexf" or "dot", good_only (default true)], the graph as of the last summary
+    register(&mut m, "exportgraph", |p, c| {
+        let classifier = c.classifier();
+        let mut seq = p.sequence();
+        let format: Option<String> = seq.optional_next().unwrap_or(None);
+        let good_only: bool = seq.optional_next().unwrap_or(None).unwrap_or(true);
+        let format = format.as_deref().unwrap_or("graphml").parse::<GraphFormat>().map_err(Error::Custom)?;
+        Ok(export_graph(&c.nodes.lock(), &c.conns.lock(), &classifier, &c.geo, good_only, format))
+    });
+
+    // the effective crawler config, file plus flag overrides
+    register(&mut m, "getconfig", |_, c| Ok(c.settings.get().config.clone()));
+
+    // params: [token], rereads the config file and classifier rules and swaps them in, needs rpc.admin_token
+    register(&mut m, "reloadconfig", |p, c| {
+        let token: Option<String> = p.sequence().optional_next().unwrap_or(None);
+        let expected = c.settings.get().config.rpc.admin_token.clone().ok_or_else(|| Error::Custom("reloadconfig is disabled, set rpc.admin_token".into()))?;
+        if !token.map_or(false, |t| token_matches(&t, &expected)) { return Err(Error::Custom("bad token".into())); }
+        match c.settings.reload() {
+            Ok(s) => { info!("config reloaded over rpc"); Ok(s.config.clone()) }
+            Err(e) => { error!("config reload failed, keeping the old one: {}", e); Err(Error::Custom(e)) }
         }
-        Ok(Stats { num_known_nodes: nodes.len(), num_contacted_nodes: contacted, num_relevant_zcash_nodes: relevant,
-            num_zcashd_nodes: zd, num_zebra_nodes: zb, num_flux_nodes: fx, num_other_nodes: ot,
-            tip_height_estimate: tip, crawler_runtime_secs: c.summary.lock().crawler_runtime.as_secs() })
-    }).unwrap();
+    });
 
-    m.register_method("getnodes", |p, c| {
-        let show_flux: bool = p.parse().unwrap_or(false);
+    register(&mut m, "getstatshistory", |p, c| {
+        let mut seq = p.sequence();
+        let from: Option<u64> = seq.optional_next().unwrap_or(None);
+        let to: Option<u64> = seq.optional_next().unwrap_or(None);
+        let resolution: Option<u64> = seq.optional_next().unwrap_or(None);
+        Ok(c.history.lock().query(from.unwrap_or(0), to.unwrap_or(u64::MAX), resolution.unwrap_or(0)))
+    });
+
+    // params: [show_flux, uptime window ("2h", "8h", "1d", "7d", "30d"), min uptime 0..1]
+    register(&mut m, "getnodes", |p, c| {
+        let classifier = c.classifier();
+        let mut seq = p.sequence();
+        let show_flux: bool = seq.optional_next().unwrap_or(None).unwrap_or(false);
+        let window: Option<UptimeWindow> = seq.optional_next().unwrap_or(None);
+        let min_uptime: Option<f64> = seq.optional_next().unwrap_or(None);
+        let uptime_filter = window.map(|w| (w, min_uptime.unwrap_or(0.5)));
         let rt = c.summary.lock().crawler_runtime.as_secs();
         let nodes = c.nodes.lock();
-        let tip = get_tip(&nodes);
-
-        let mut stats = Stats { num_known_nodes: nodes.len(), num_contacted_nodes: 0, num_relevant_zcash_nodes: 0,
-            num_zcashd_nodes: 0, num_zebra_nodes: 0, num_flux_nodes: 0, num_other_nodes: 0, tip_height_estimate: tip, crawler_runtime_secs: rt };
+        let tip = classifier.tip(&nodes);
+        let live_tip = c.chain.read().live_height();
+        let stats = compute_stats(&nodes, &classifier, rt);
+        let aliases = Aliases::find(&nodes);
         let mut out = Vec::new();
 
         for (addr, n) in nodes.iter() {
             if n.user_agent.is_none() { continue; }
-            stats.num_contacted_nodes += 1;
             let ua = n.user_agent.as_ref().map(|x| x.0.clone()).unwrap_or_default();
-            let (t, flux) = client_type(&ua);
-            let good = is_good_node(n, tip);
-
-            match t.as_str() { "zcashd" => stats.num_zcashd_nodes += 1, "zebra" => stats.num_zebra_nodes += 1, "flux" => stats.num_flux_nodes += 1, _ => stats.num_other_nodes += 1 }
-            if good { stats.num_relevant_zcash_nodes += 1; }
+            let cl = classifier.classify(addr, n, tip, uptime_filter);
+            let flux = cl.fork.is_some();
 
             if !show_flux && flux { continue; }
-            if !show_flux && t != "zcashd" && t != "zebra" { continue; }
+            if !show_flux && cl.client != "zcashd" && cl.client != "zebra" { continue; }
 
             out.push(NodeInfo {
                 ip: addr.ip().to_string(), port: addr.port(),
                 protocol_version: n.protocol_version.map(|v| v.0), user_agent: Some(ua),
-                height: n.start_height, services: n.services,
+                height: n.start_height, services: n.services.map(|s| s.bits()),
+                service_flags: n.services.map(|s| s.names()).unwrap_or_default(), gossip_services: n.gossip_services.map(|s| s.bits()),
                 last_seen_secs: n.last_connected.map(|t| t.elapsed().as_secs()).unwrap_or(u64::MAX),
-                is_relevant: good, is_flux: flux, client_type: t,
+                is_relevant: cl.is_relevant(), is_flux: flux, filter_reason: cl.reason(), client_type: cl.client,
+                uptime: n.reachability.scores(),
+                handshake_ms: n.handshake_time.map(|d| d.as_millis() as u64), clock_offset_secs: n.clock_offset,
+                lag_blocks: live_tip.zip(n.announced_height).map(|(t, h)| t - h), aliases: aliases.aliases_of(addr), latency: n.latency.summary(),
+                handshake_failures: n.handshake_failures.clone(), last_failure: n.last_failure,
+                geo: c.geo.lookup(addr.ip()),
             });
         }
         out.sort_by(|a, b| b.is_relevant.cmp(&a.is_relevant).then(a.last_seen_secs.cmp(&b.last_seen_secs)));
         Ok(NodesResponse { stats, nodes: out })
-    }).unwrap();
+    });
+
+    // params: [relevant_only], version counts per implementation from the parsed user agents
+    register(&mut m, "getversions", |p, c| {
+        let classifier = c.classifier();
+        let relevant_only: bool = p.sequence().optional_next().unwrap_or(None).unwrap_or(false);
+        let nodes = c.nodes.lock();
+        let tip = classifier.tip(&nodes);
+        let agents = nodes.iter().filter(|(a, n)| !relevant_only || classifier.is_good_node(a, n, tip, None)).filter_map(|(_, n)| n.parsed_user_agent.as_ref());
+        Ok(version_histograms(agents))
+    });
+
+    // params: [relevant_only], nodes per service combination and how gossip compares to version
+    register(&mut m, "getservices", |p, c| {
+        let classifier = c.classifier();
+        let relevant_only: bool = p.sequence().optional_next().unwrap_or(None).unwrap_or(false);
+        let nodes = c.nodes.lock();
+        let tip = classifier.tip(&nodes);
+        Ok(service_stats(nodes.iter().filter(|(a, n)| !relevant_only || classifier.is_good_node(a, n, tip, None)).map(|(_, n)| n)))
+    });
 
-    m.register_method("getgeonodes", |_, c| {
+    // params: [outlier threshold secs], distribution of peer clock offsets from their version timestamps
+    register(&mut m, "getclockskew", |p, c| {
+        let outlier: Option<i64> = p.sequence().optional_next().unwrap_or(None);
+        Ok(clock_skew_stats(&c.nodes.lock(), outlier.unwrap_or(DEFAULT_SKEW_OUTLIER_SECS)))
+    });
+
+    // params: [group by "country" (default) or "asn"]
+    register(&mut m, "getlatency", |p, c| {
+        let by: Option<String> = p.sequence().optional_next().unwrap_or(None);
+        Ok(latency_by_location(&c.nodes.lock(), &c.geo, by.as_deref() == Some("asn")))
+    });
+
+    register(&mut m, "getgeonodes", |_, c| {
+        let classifier = c.classifier();
         let nodes = c.nodes.lock();
-        let tip = get_tip(&nodes);
+        let tip = classifier.tip(&nodes);
         let mut out = Vec::new();
 
         for (addr, n) in nodes.iter() {
-            if n.user_agent.is_none() { continue; }
-            let ua = n.user_agent.as_ref().map(|x| x.0.clone()).unwrap_or_default();
-            let (t, flux) = client_type(&ua);
-            let good = is_good_node(n, tip);
-
-            if flux { continue; }
-            if t != "zcashd" && t != "zebra" { continue; }
-            if !good { continue; }
+            let cl = classifier.classify(addr, n, tip, None);
+            if !cl.is_relevant() { continue; }
 
             out.push(NodeGeo {
                 ip: addr.ip().to_string(),
-                client_type: t,
+                client_type: cl.client,
                 height: n.start_height,
+                geo: c.geo.lookup(addr.ip()),
             });
         }
         Ok(out)
-    }).unwrap();
+    });
 
-    m.register_method("getdiagnostics", |_, c| {
+    register(&mut m, "getdiagnostics", |_, c| {
+        let classifier = c.classifier();
         let nodes = c.nodes.lock();
-        let tip = get_tip(&nodes);
-        
-        let mut no_ua = 0;
-        let mut flux_count = 0;
-        let mut height_filtered = 0;
-        let mut zebra_sync_filtered = 0;
-        let mut passed = 0;
-        let mut zcashd = 0;
-        let mut zebra = 0;
-        
-        for n in nodes.values() {
-            if n.user_agent.is_none() {
-                no_ua += 1;
-                continue;
-            }
-            
-            let ua = n.user_agent.as_ref().map(|x| x.0.to_lowercase()).unwrap_or_default();
-            
-            // Check flux
-            if ua.contains("flux") {
-                flux_count += 1;
-                continue;
-            }
-            
-            if ua.contains("magicbean") {
-                if let Some(i) = ua.find("magicbean:") {
-                    let ver = &ua[i+10..];
-                    if let Some(d) = ver.find('.') {
-                        if let Ok(maj) = ver[..d].parse::<u32>() {
-                            if maj >= 6 {
-                                flux_count += 1;
-                                continue;
-                            }
-                        }
-                    }
-                }
-            }
-            
-            // Check height
-            let h = n.start_height.unwrap_or(0);
-            if h < MIN_HEIGHT {
-                height_filtered += 1;
-                continue;
-            }
-            
-            // Check zebra sync tolerance
-            if ua.contains("zebra") {
-                if (h - tip).abs() > HEIGHT_TOLERANCE {
-                    zebra_sync_filtered += 1;
-                    continue;
-                }
-                zebra += 1;
-            } else if ua.contains("magicbean") {
-                zcashd += 1;
-            }
-            
-            passed += 1;
-        }
-        
+        let counts = classifier.count(&nodes);
+        let reason = |k: &str| counts.by_reason.get(k).copied().unwrap_or(0);
+        let client = |k: &str| counts.by_client.get(k).copied().unwrap_or(0);
+        let contacted = nodes.len() - reason("no_user_agent");
+
         Ok(DiagnosticInfo {
             total_known: nodes.len(),
-            total_contacted: nodes.values().filter(|n| n.user_agent.is_some()).count(),
-            filtered_by_no_ua: no_ua,
-            filtered_by_flux: flux_count,
-            filtered_by_height: height_filtered,
-            filtered_by_zebra_sync: zebra_sync_filtered,
-            passed_filters: passed,
-            zcashd_nodes: zcashd,
-            zebra_nodes: zebra,
+            total_contacted: contacted,
+            filtered_by_no_ua: reason("no_user_agent"),
+            filtered_by_flux: reason("fork"),
+            filtered_by_height: reason("below_min_height"),
+            filtered_by_zebra_sync: reason("off_tip"),
+            passed_filters: reason("relevant"),
+            zcashd_nodes: client("zcashd"),
+            zebra_nodes: client("zebra"),
+            filtered_by_port: reason("wrong_port"),
+            filtered_by_unknown_client: reason("unknown_client"),
+            tip_height_estimate: classifier.tip(&nodes),
+            counts,
         })
-    }).unwrap();
+    });
 
     m
 }
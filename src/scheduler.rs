@@ -0,0 +1,127 @@
+// crawl scheduler - picks which known addresses get a connection attempt each crawl round
+// failures back off exponentially, addresses that never answer get evicted, fresh gossip and stable nodes go first
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use serde::{Deserialize, Serialize};
+use crate::network::{unix_now, KnownNode, UptimeWindow};
+
+// gossip younger than this counts as fresh
+const FRESH_GOSSIP_SECS: u64 = 3600;
+// stability is judged on this window
+const STABILITY_WINDOW: UptimeWindow = UptimeWindow::D7;
+
+// the [retry] table of the crawler config
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    pub retry_base_secs: u64,
+    pub retry_max_secs: u64,
+    // 0 keeps addresses forever
+    pub evict_after_days: u64,
+    pub max_attempts_per_round: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { retry_base_secs: 45, retry_max_secs: 6 * 3600, evict_after_days: 14, max_attempts_per_round: 2000 }
+    }
+}
+
+#[derive(Default)]
+pub struct CrawlPlan {
+    // most useful first
+    pub targets: Vec<SocketAddr>,
+    pub evict: Vec<SocketAddr>,
+    // due but left for a later round by max_attempts_per_round
+    pub deferred: usize,
+}
+
+pub struct Scheduler { pub policy: RetryPolicy }
+
+impl Scheduler {
+    pub fn new(policy: RetryPolicy) -> Self { Self { policy } }
+
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let secs = self.policy.retry_base_secs.saturating_mul(1u64.checked_shl(failures).unwrap_or(u64::MAX));
+        Duration::from_secs(secs.min(self.policy.retry_max_secs.max(self.policy.retry_base_secs)))
+    }
+
+    // never tried is always due, otherwise the backoff counts from the last attempt
+    pub fn is_due(&self, n: &KnownNode, now_unix: u64) -> bool {
+        let last = n.reachability.last_update;
+        last == 0 || now_unix.saturating_sub(last) >= self.backoff(n.connection_failures).as_secs()
+    }
+
+    // no success within evict_after_days, counted from when we first heard of the node if it never answered
+    pub fn should_evict(&self, n: &KnownNode) -> bool {
+        if self.policy.evict_after_days == 0 { return false; }
+        let idle = match (n.last_connected, n.first_seen) {
+            (Some(t), _) => t.elapsed().as_secs(),
+            (None, Some(f)) => unix_now().saturating_sub(f),
+            _ => return false,
+        };
+        idle > self.policy.evict_after_days * 86_400
+    }
+
+    // higher goes first: stable nodes keep their place, fresh gossip and unknown addrs get looked at quickly
+    pub fn priority(&self, n: &KnownNode) -> f64 {
+        let fresh = n.last_gossip.map_or(false, |t| t.elapsed().as_secs() < FRESH_GOSSIP_SECS);
+        let untried = n.reachability.last_update == 0;
+        2.0 * n.reachability.score(STABILITY_WINDOW) + if fresh { 1.0 } else { 0.0 } + if untried { 1.5 } else { 0.0 }
+            - 0.1 * n.connection_failures.min(10) as f64
+    }
+
+    // skip is for addrs handled elsewhere, like connected or tip peers, they are neither dialed nor evicted
+    pub fn plan(&self, nodes: &HashMap<SocketAddr, KnownNode>, skip: impl Fn(&SocketAddr, &KnownNode) -> bool) -> CrawlPlan {
+        let now = unix_now();
+        let mut out = CrawlPlan::default();
+        let mut due = Vec::new();
+        for (a, n) in nodes {
+            if skip(a, n) { continue; }
+            if self.should_evict(n) { out.evict.push(*a); continue; }
+            // random jitter so equal priorities dont always pick the same addrs
+            if self.is_due(n, now) { due.push((self.priority(n) + rand::random::<f64>() * 0.01, *a)); }
+        }
+        due.sort_by(|x, y| y.0.total_cmp(&x.0));
+        out.deferred = due.len().saturating_sub(self.policy.max_attempts_per_round);
+        out.targets = due.into_iter().take(self.policy.max_attempts_per_round).map(|(_, a)| a).collect();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn addr(i: u8) -> SocketAddr { SocketAddr::from(([10, 0, 0, i], 8233)) }
+
+    #[test]
+    fn backoff_eviction_and_order() {
+        let s = Scheduler::new(RetryPolicy { max_attempts_per_round: 2, ..Default::default() });
+        assert_eq!(s.backoff(0), Duration::from_secs(45));
+        assert_eq!(s.backoff(3), Duration::from_secs(360));
+        assert_eq!(s.backoff(200), Duration::from_secs(6 * 3600));
+
+        let now = unix_now();
+        let mut nodes = HashMap::new();
+        // failed 4 times, last try a minute ago, backing off
+        let mut failing = KnownNode { connection_failures: 4, first_seen: Some(now), ..Default::default() };
+        failing.reachability.update(false, now - 60);
+        nodes.insert(addr(1), failing);
+        // stable node last tried an hour ago
+        let mut stable = KnownNode { last_connected: Some(Instant::now()), ..Default::default() };
+        stable.reachability.update(true, now - 3600);
+        nodes.insert(addr(2), stable);
+        // fresh gossip never tried
+        nodes.insert(addr(3), KnownNode { first_seen: Some(now), last_gossip: Some(Instant::now()), ..Default::default() });
+        // never answered in the month since we heard of it
+        nodes.insert(addr(4), KnownNode { first_seen: Some(now - 30 * 86_400), ..Default::default() });
+        nodes.insert(addr(5), KnownNode::default());
+
+        let p = s.plan(&nodes, |a, _| *a == addr(5));
+        assert_eq!(p.evict, vec![addr(4)]);
+        assert_eq!(p.targets, vec![addr(3), addr(2)]);
+        assert_eq!(p.deferred, 0);
+    }
+}
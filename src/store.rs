@@ -0,0 +1,245 @@
+// node store - keeps known nodes and connections on disk so restarts dont start from scratch
+
+use std::{net::SocketAddr, path::Path, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ziggurat_core_crawler::connection::KnownConnection;
+use ziggurat_zcash::protocol::payload::{ProtocolVersion, ServiceFlags, VarStr};
+use crate::{network::{unix_now, FailureCounts, HandshakeFailure, KnownNetwork, KnownNode, Latency, Reachability}, user_agent::UserAgent};
+
+const NODES_TREE: &str = "nodes";
+const CONNECTIONS_TREE: &str = "connections";
+
+// instants dont survive a restart, so everything time related is stored as unix secs
+#[derive(Serialize, Deserialize)]
+struct StoredNode {
+    last_connected: Option<u64>,
+    handshake_time_ms: Option<u64>,
+    protocol_version: Option<u32>,
+    user_agent: Option<String>,
+    start_height: Option<i32>,
+    services: Option<u64>,
+    #[serde(default)]
+    gossip_services: Option<u64>,
+    connection_failures: u32,
+    #[serde(default)]
+    first_seen: Option<u64>,
+    #[serde(default)]
+    reachability: Reachability,
+    #[serde(default)]
+    handshake_failures: FailureCounts,
+    #[serde(default)]
+    last_failure: Option<HandshakeFailure>,
+    #[serde(default)]
+    remote_timestamp: Option<i64>,
+    #[serde(default)]
+    remote_nonce: Option<u64>,
+    #[serde(default)]
+    clock_offset: Option<i64>,
+    #[serde(default)]
+    remote_addr_from: Option<SocketAddr>,
+    #[serde(default)]
+    latency: Latency,
+    #[serde(default)]
+    announced_height: Option<i32>,
+    #[serde(default)]
+    announce_lag: Latency,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredConnection { a: SocketAddr, b: SocketAddr, last_seen: u64 }
+
+pub fn to_unix(t: Instant) -> u64 {
+    SystemTime::now().checked_sub(t.elapsed()).unwrap_or(UNIX_EPOCH).duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// on some platforms instants count ticks from boot, a time from before the host came up is
+// clamped to the oldest instant there is instead of being lost
+pub fn from_unix(secs: u64) -> Instant {
+    let age = SystemTime::now().duration_since(UNIX_EPOCH + Duration::from_secs(secs)).unwrap_or_default();
+    let now = Instant::now();
+    now.checked_sub(age).unwrap_or_else(|| oldest_instant(now))
+}
+
+fn oldest_instant(now: Instant) -> Instant {
+    let (mut lo, mut hi) = (Duration::ZERO, Duration::MAX);
+    while hi - lo > Duration::from_millis(1) {
+        let mid = lo + (hi - lo) / 2;
+        if now.checked_sub(mid).is_some() { lo = mid } else { hi = mid }
+    }
+    now - lo
+}
+
+impl From<&KnownNode> for StoredNode {
+    fn from(n: &KnownNode) -> Self {
+        Self {
+            last_connected: n.last_connected.map(to_unix),
+            handshake_time_ms: n.handshake_time.map(|d| d.as_millis() as u64),
+            protocol_version: n.protocol_version.map(|v| v.0),
+            user_agent: n.user_agent.as_ref().map(|x| x.0.clone()),
+            start_height: n.start_height,
+            services: n.services.map(|s| s.bits()),
+            gossip_services: n.gossip_services.map(|s| s.bits()),
+            connection_failures: n.connection_failures,
+            first_seen: n.first_seen,
+            reachability: n.reachability.clone(),
+            handshake_failures: n.handshake_failures.clone(),
+            last_failure: n.last_failure,
+            remote_timestamp: n.remote_timestamp,
+            remote_nonce: n.remote_nonce,
+            clock_offset: n.clock_offset,
+            remote_addr_from: n.remote_addr_from,
+            latency: n.latency.clone(),
+            announced_height: n.announced_height,
+            announce_lag: n.announce_lag.clone(),
+        }
+    }
+}
+
+impl From<StoredNode> for KnownNode {
+    fn from(s: StoredNode) -> Self {
+        Self {
+            last_connected: s.last_connected.map(from_unix),
+            handshake_time: s.handshake_time_ms.map(Duration::from_millis),
+            protocol_version: s.protocol_version.map(ProtocolVersion),
+            parsed_user_agent: s.user_agent.as_deref().map(UserAgent::parse),
+            user_agent: s.user_agent.map(VarStr),
+            start_height: s.start_height,
+            services: s.services.map(ServiceFlags::from),
+            gossip_services: s.gossip_services.map(ServiceFlags::from),
+            connection_failures: s.connection_failures,
+            // older dbs didnt record it, give those nodes a fresh grace period before eviction
+            first_seen: s.first_seen.or_else(|| Some(unix_now())),
+            reachability: s.reachability,
+            handshake_failures: s.handshake_failures,
+            last_failure: s.last_failure,
+            remote_timestamp: s.remote_timestamp,
+            remote_nonce: s.remote_nonce,
+            clock_offset: s.clock_offset,
+            remote_addr_from: s.remote_addr_from,
+            latency: s.latency,
+            announced_height: s.announced_height,
+            announce_lag: s.announce_lag,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NodeStore { db: sled::Db }
+
+impl NodeStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
+        Ok(Self { db: sled::open(path)? })
+    }
+
+    // fills the network with whatever was saved last time, returns (nodes, connections) loaded.
+    // connections older than max_edge_age are skipped, None keeps them all for offline exports of a stopped crawler
+    pub fn load_with(&self, net: &KnownNetwork, max_edge_age: Option<u64>) -> sled::Result<(usize, usize)> {
+        let mut num_nodes = 0;
+        {
+            let mut nodes = net.nodes.write();
+            for kv in self.db.open_tree(NODES_TREE)?.iter() {
+                let (k, v) = kv?;
+                let addr = match std::str::from_utf8(&k).ok().and_then(|x| x.parse::<SocketAddr>().ok()) { Some(a) => a, None => continue };
+                match serde_json::from_slice::<StoredNode>(&v) {
+                    Ok(s) => { nodes.insert(addr, s.into()); num_nodes += 1; }
+                    Err(e) => warn!("bad stored node {}: {}", addr, e),
+                }
+            }
+        }
+
+        let mut num_conns = 0;
+        let mut conns = net.connections.write();
+        for kv in self.db.open_tree(CONNECTIONS_TREE)?.iter() {
+            let (_, v) = kv?;
+            let s: StoredConnection = match serde_json::from_slice(&v) { Ok(s) => s, Err(_) => continue };
+            // expired edges would be dropped by the next summary anyway
+            // compared in unix secs, a clamped instant would look younger than it is
+            if max_edge_age.map_or(false, |m| unix_now().saturating_sub(s.last_seen) > m) { continue; }
+            let mut c = KnownConnection::new(s.a, s.b);
+            c.last_seen = from_unix(s.last_seen);
+            conns.insert(c);
+            num_conns += 1;
+        }
+        Ok((num_nodes, num_conns))
+    }
+
+    pub fn checkpoint(&self, net: &KnownNetwork) -> sled::Result<()> {
+        let nodes = net.nodes();
+        let tree = self.db.open_tree(NODES_TREE)?;
+        let mut batch = sled::Batch::default();
+        for (addr, n) in nodes.iter() {
+            if let Ok(v) = serde_json::to_vec(&StoredNode::from(n)) { batch.insert(addr.to_string().as_bytes(), v); }
+        }
+        // evicted nodes go from disk too
+        for k in tree.iter().keys() {
+            let k = k?;
+            if !std::str::from_utf8(&k).ok().and_then(|x| x.parse::<SocketAddr>().ok()).map_or(false, |a| nodes.contains_key(&a)) { batch.remove(k); }
+        }
+        tree.apply_batch(batch)?;
+
+        let conns = net.connections();
+        let tree = self.db.open_tree(CONNECTIONS_TREE)?;
+        let mut batch = sled::Batch::default();
+        let mut live = std::collections::HashSet::with_capacity(conns.len());
+        for c in conns.iter() {
+            let key = format!("{}|{}", c.a, c.b);
+            let s = StoredConnection { a: c.a, b: c.b, last_seen: to_unix(c.last_seen) };
+            if let Ok(v) = serde_json::to_vec(&s) { batch.insert(key.as_bytes(), v); }
+            live.insert(key);
+        }
+        // connections get pruned in memory, mirror that on disk
+        for k in tree.iter().keys() {
+            let k = k?;
+            if !std::str::from_utf8(&k).map_or(false, |x| live.contains(x)) { batch.remove(k); }
+        }
+        tree.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_prunes() {
+        let store = NodeStore { db: sled::Config::new().temporary(true).open().unwrap() };
+        let (a, b, c): (SocketAddr, SocketAddr, SocketAddr) = ("1.1.1.1:8233".parse().unwrap(), "2.2.2.2:8233".parse().unwrap(), "3.3.3.3:8233".parse().unwrap());
+        let net = KnownNetwork::default();
+        net.add_addrs(a, &[b, c]);
+        if let Some(n) = net.nodes.write().get_mut(&a) {
+            n.user_agent = Some(VarStr("/MagicBean:6.0.0/".into()));
+            n.start_height = Some(3_000_000);
+            n.connection_failures = 2;
+        }
+        store.checkpoint(&net).unwrap();
+
+        // c got evicted, its node and edge have to go from disk on the next checkpoint
+        net.remove_nodes(&[c]);
+        net.connections.write().retain(|x| x.a != c && x.b != c);
+        store.checkpoint(&net).unwrap();
+
+        let loaded = KnownNetwork::default();
+        assert_eq!(store.load_with(&loaded, None).unwrap(), (2, 1));
+        let nodes = loaded.nodes();
+        assert!(!nodes.contains_key(&c));
+        let n = &nodes[&a];
+        assert_eq!(n.user_agent.as_ref().map(|x| x.0.as_str()), Some("/MagicBean:6.0.0/"));
+        assert_eq!(n.parsed_user_agent.as_ref().map(|x| x.components[0].name.as_str()), Some("MagicBean"));
+        assert_eq!((n.start_height, n.connection_failures), (Some(3_000_000), 2));
+        assert!(loaded.connections().iter().all(|x| x.a != c && x.b != c));
+    }
+
+    #[test]
+    fn clamps_times_from_before_boot() {
+        let now = Instant::now();
+        let oldest = oldest_instant(now);
+        assert!(oldest <= now && oldest.checked_sub(Duration::from_secs(1)).is_none());
+        assert!(from_unix(0) < now);
+        let recent = from_unix(unix_now() - 5);
+        assert!((4..=6).contains(&recent.elapsed().as_secs()));
+    }
+}
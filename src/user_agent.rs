@@ -0,0 +1,179 @@
+// user agent parsing - bip-14 style "/Name:Version(comment; comment)/Name:Version/" strings
+
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+use serde::Serialize;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    // e.g. "rc.4" for 1.0.0-rc.4
+    pub pre: Option<String>,
+}
+
+impl Version {
+    // lenient, missing minor/patch are 0 and build metadata after + is dropped
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.split('+').next()?.trim();
+        let (core, pre) = match s.split_once('-') { Some((c, p)) => (c, Some(p.to_string())), None => (s, None) };
+        let mut parts = core.split('.');
+        let mut num = || parts.next().map(|p| p.parse::<u64>().ok()).unwrap_or(Some(0));
+        let (major, minor, patch) = (num()?, num()?, num()?);
+        Some(Self { major, minor, patch, pre })
+    }
+}
+
+// semver precedence of dot separated prerelease identifiers: numeric ones compare as numbers and
+// sort before alphanumeric ones, a longer list wins when all shared ones are equal
+fn cmp_pre(a: &str, b: &str) -> Ordering {
+    let (mut x, mut y) = (a.split('.'), b.split('.'));
+    loop {
+        let o = match (x.next(), y.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(p), Some(q)) => match (p.parse::<u64>(), q.parse::<u64>()) {
+                // the string compare only matters for leading zeros, it keeps cmp in line with eq
+                (Ok(m), Ok(n)) => m.cmp(&n).then_with(|| p.cmp(q)),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                _ => p.cmp(q),
+            },
+        };
+        if o != Ordering::Equal { return o; }
+    }
+}
+
+// prereleases sort before the release, like semver
+impl Ord for Version {
+    fn cmp(&self, o: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(o.major, o.minor, o.patch)).then_with(|| match (&self.pre, &o.pre) {
+            (None, None) => Ordering::Equal, (None, Some(_)) => Ordering::Greater, (Some(_), None) => Ordering::Less, (Some(a), Some(b)) => cmp_pre(a, b),
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(ref p) = self.pre { write!(f, "-{}", p)?; }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UaComponent {
+    pub name: String,
+    // None when there was no version or it didnt parse, raw keeps whatever was there
+    pub version: Option<Version>,
+    pub raw_version: String,
+    pub comments: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct UserAgent { pub components: Vec<UaComponent> }
+
+impl UserAgent {
+    pub fn parse(ua: &str) -> Self {
+        let mut components = Vec::new();
+        let mut rest = ua.trim();
+        while !rest.is_empty() {
+            rest = rest.trim_start_matches('/');
+            if rest.is_empty() { break; }
+            // comments can contain '/', so find the end of the component outside of parens
+            let mut depth = 0;
+            let end = rest.char_indices().find(|(_, c)| match c { '(' => { depth += 1; false } ')' => { depth -= 1; false } '/' => depth <= 0, _ => false })
+                .map_or(rest.len(), |(i, _)| i);
+            let (part, tail) = rest.split_at(end);
+            rest = tail;
+
+            let (head, comments) = match part.find('(') {
+                Some(i) => (&part[..i], part[i + 1..].trim_end_matches(')').split(';').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()),
+                None => (part, Vec::new()),
+            };
+            let (name, raw) = head.split_once(':').unwrap_or((head, ""));
+            components.push(UaComponent { name: name.trim().to_string(), version: Version::parse(raw), raw_version: raw.trim().to_string(), comments });
+        }
+        Self { components }
+    }
+
+    // the first component names the node implementation, later ones are usually wrappers or forks
+    pub fn implementation(&self) -> Option<&UaComponent> { self.components.first() }
+}
+
+// counts for one implementation at three levels of detail
+#[derive(Clone, Default, Serialize)]
+pub struct VersionHistogram {
+    pub implementation: String,
+    pub total: usize,
+    pub unparsed: usize,
+    pub by_major: BTreeMap<String, usize>,
+    pub by_minor: BTreeMap<String, usize>,
+    pub by_version: BTreeMap<String, usize>,
+}
+
+pub fn version_histograms<'a, I: IntoIterator<Item = &'a UserAgent>>(agents: I) -> Vec<VersionHistogram> {
+    let mut out: BTreeMap<String, VersionHistogram> = BTreeMap::new();
+    for ua in agents {
+        let c = match ua.implementation() { Some(c) => c, None => continue };
+        let h = out.entry(c.name.clone()).or_insert_with(|| VersionHistogram { implementation: c.name.clone(), ..Default::default() });
+        h.total += 1;
+        match c.version {
+            Some(ref v) => {
+                *h.by_major.entry(format!("{}.x.y", v.major)).or_default() += 1;
+                *h.by_minor.entry(format!("{}.{}.y", v.major, v.minor)).or_default() += 1;
+                *h.by_version.entry(v.to_string()).or_default() += 1;
+            }
+            None => h.unparsed += 1,
+        }
+    }
+    let mut out: Vec<_> = out.into_values().collect();
+    out.sort_by(|a, b| b.total.cmp(&a.total));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_components() {
+        let ua = UserAgent::parse("/Zebra:1.0.0-rc.4/");
+        assert_eq!(ua.components.len(), 1);
+        assert_eq!(ua.components[0].name, "Zebra");
+        assert_eq!(ua.components[0].version, Some(Version { major: 1, minor: 0, patch: 0, pre: Some("rc.4".into()) }));
+
+        let ua = UserAgent::parse("/MagicBean:5.4.2(bitcore; a/b)/Wrapper:0.1/");
+        assert_eq!(ua.components.len(), 2);
+        assert_eq!(ua.components[0].comments, vec!["bitcore".to_string(), "a/b".to_string()]);
+        assert_eq!(ua.components[0].version.as_ref().unwrap().to_string(), "5.4.2");
+        assert_eq!(ua.components[1].version.as_ref().unwrap().to_string(), "0.1.0");
+
+        let ua = UserAgent::parse("/weird:v1/");
+        assert_eq!(ua.components[0].version, None);
+        assert_eq!(ua.components[0].raw_version, "v1");
+        assert!(UserAgent::parse("").components.is_empty());
+    }
+
+    #[test]
+    fn version_order_and_histograms() {
+        assert!(Version::parse("1.0.0-rc.4").unwrap() < Version::parse("1.0.0").unwrap());
+        assert!(Version::parse("1.0.0-rc.9").unwrap() < Version::parse("1.0.0-rc.10").unwrap());
+        assert!(Version::parse("1.0.0-rc").unwrap() < Version::parse("1.0.0-rc.1").unwrap());
+        assert!(Version::parse("1.0.0-1").unwrap() < Version::parse("1.0.0-alpha").unwrap());
+        assert!(Version::parse("1.0.0-alpha.beta").unwrap() < Version::parse("1.0.0-beta").unwrap());
+        assert!(Version::parse("1.10.0").unwrap() > Version::parse("1.9.3").unwrap());
+
+        let uas: Vec<_> = ["/Zebra:1.9.0/", "/Zebra:1.9.0/", "/Zebra:2.0.1/", "/MagicBean:5.10.0/"].iter().map(|s| UserAgent::parse(s)).collect();
+        let h = version_histograms(&uas);
+        assert_eq!(h[0].implementation, "Zebra");
+        assert_eq!(h[0].by_major.get("1.x.y"), Some(&2));
+        assert_eq!(h[0].by_version.get("2.0.1"), Some(&1));
+        assert_eq!(h[1].total, 1);
+    }
+}
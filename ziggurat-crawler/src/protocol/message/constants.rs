@@ -16,15 +16,21 @@ pub const PROTOCOL_VERSION: u32 = 170_140;
 /// The current network version identifier.
 pub const MAGIC_TESTNET: [u8; MAGIC_LEN] = [0xfa, 0x1a, 0xf9, 0xbf];
 pub const MAGIC_MAINNET: [u8; MAGIC_LEN] = [0x24, 0xe9, 0x27, 0x64];
+pub const MAGIC_REGTEST: [u8; MAGIC_LEN] = [0xaa, 0xe8, 0x3f, 0x5f];
+
+/// Default p2p ports, see [`Network::default_port`](crate::protocol::network::Network::default_port).
+pub const P2P_DEFAULT_MAINNET_PORT: u16 = 8233;
+pub const P2P_DEFAULT_TESTNET_PORT: u16 = 18233;
+pub const P2P_DEFAULT_REGTEST_PORT: u16 = 18344;
 
 /// Version message user agent
 pub const USER_AGENT: &str = "MagicBean:5.4.2";
 
+/// The magic of the default [`Network`](crate::protocol::network::Network), use
+/// [`Network::magic`](crate::protocol::network::Network::magic) to pick one at runtime.
 #[cfg(test)]
 pub const MAGIC: [u8; MAGIC_LEN] = MAGIC_TESTNET;
-#[cfg(all(not(test), not(feature = "crawler")))]
-pub const MAGIC: [u8; MAGIC_LEN] = MAGIC_MAINNET;
-#[cfg(all(not(test), feature = "crawler"))]
+#[cfg(not(test))]
 pub const MAGIC: [u8; MAGIC_LEN] = MAGIC_MAINNET;
 
 pub const COMMAND_LEN: usize = 12;
@@ -36,6 +42,8 @@ pub const PING_COMMAND: [u8; COMMAND_LEN] = *b"ping\0\0\0\0\0\0\0\0";
 pub const PONG_COMMAND: [u8; COMMAND_LEN] = *b"pong\0\0\0\0\0\0\0\0";
 pub const GETADDR_COMMAND: [u8; COMMAND_LEN] = *b"getaddr\0\0\0\0\0";
 pub const ADDR_COMMAND: [u8; COMMAND_LEN] = *b"addr\0\0\0\0\0\0\0\0";
+pub const ADDRV2_COMMAND: [u8; COMMAND_LEN] = *b"addrv2\0\0\0\0\0\0";
+pub const SENDADDRV2_COMMAND: [u8; COMMAND_LEN] = *b"sendaddrv2\0\0";
 pub const GETHEADERS_COMMAND: [u8; COMMAND_LEN] = *b"getheaders\0\0";
 pub const HEADERS_COMMAND: [u8; COMMAND_LEN] = *b"headers\0\0\0\0\0";
 pub const GETBLOCKS_COMMAND: [u8; COMMAND_LEN] = *b"getblocks\0\0\0";
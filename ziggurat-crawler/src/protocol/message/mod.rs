@@ -12,7 +12,7 @@ use crate::protocol::{
     payload::{
         block::{Block, Headers, LocatorHashes},
         codec::Codec,
-        Addr, FilterAdd, FilterLoad, Inv, Nonce, Reject, Tx, Version,
+        Addr, AddrV2, FilterAdd, FilterLoad, Inv, Nonce, Reject, Tx, Version,
     },
 };
 
@@ -62,8 +62,14 @@ impl Codec for MessageHeader {
 impl MessageHeader {
     /// Returns a `MessageHeader` constructed from the message body.
     pub fn new(command: [u8; COMMAND_LEN], body: &[u8]) -> Self {
+        Self::with_magic(MAGIC, command, body)
+    }
+
+    /// Returns a `MessageHeader` for the network identified by `magic`, constructed from the
+    /// message body.
+    pub fn with_magic(magic: [u8; MAGIC_LEN], command: [u8; COMMAND_LEN], body: &[u8]) -> Self {
         MessageHeader {
-            magic: MAGIC,
+            magic,
             command,
             body_length: body.len() as u32,
             checksum: checksum(body),
@@ -83,6 +89,8 @@ pub enum Message {
     Pong(Nonce),
     GetAddr,
     Addr(Addr),
+    AddrV2(AddrV2),
+    SendAddrV2,
     GetHeaders(LocatorHashes),
     Headers(Headers),
     GetBlocks(LocatorHashes),
@@ -100,15 +108,15 @@ pub enum Message {
 }
 
 macro_rules! encode_with_header_prefix {
-    ($command:expr, $buffer:expr) => {{
-        let header = MessageHeader::new($command, &[]);
+    ($magic:expr, $command:expr, $buffer:expr) => {{
+        let header = MessageHeader::with_magic($magic, $command, &[]);
         header.encode($buffer)?;
     }};
 
-    ($command:expr, $buffer:expr, $payload:expr) => {{
+    ($magic:expr, $command:expr, $buffer:expr, $payload:expr) => {{
         $payload.encode($buffer)?;
         let serialized_payload = $buffer.split_to($buffer.len()).freeze();
-        let header = MessageHeader::new($command, &serialized_payload);
+        let header = MessageHeader::with_magic($magic, $command, &serialized_payload);
         header.encode($buffer)?;
         $buffer.put_slice(&serialized_payload);
     }};
@@ -117,63 +125,78 @@ macro_rules! encode_with_header_prefix {
 impl Message {
     /// Encodes a message into the supplied buffer and returns its header.
     pub fn encode(&self, buffer: &mut BytesMut) -> io::Result<()> {
+        self.encode_with_magic(MAGIC, buffer)
+    }
+
+    /// Encodes a message for the network identified by `magic` into the supplied buffer.
+    pub fn encode_with_magic(
+        &self,
+        magic: [u8; MAGIC_LEN],
+        buffer: &mut BytesMut,
+    ) -> io::Result<()> {
         match self {
             Self::Version(version) => {
-                encode_with_header_prefix!(VERSION_COMMAND, buffer, version);
+                encode_with_header_prefix!(magic, VERSION_COMMAND, buffer, version);
             }
             Self::Verack => {
-                encode_with_header_prefix!(VERACK_COMMAND, buffer);
+                encode_with_header_prefix!(magic, VERACK_COMMAND, buffer);
             }
             Self::Ping(nonce) => {
-                encode_with_header_prefix!(PING_COMMAND, buffer, nonce);
+                encode_with_header_prefix!(magic, PING_COMMAND, buffer, nonce);
             }
             Self::Pong(nonce) => {
-                encode_with_header_prefix!(PONG_COMMAND, buffer, nonce);
+                encode_with_header_prefix!(magic, PONG_COMMAND, buffer, nonce);
             }
             Self::GetAddr => {
-                encode_with_header_prefix!(GETADDR_COMMAND, buffer);
+                encode_with_header_prefix!(magic, GETADDR_COMMAND, buffer);
             }
             Self::Addr(addr) => {
-                encode_with_header_prefix!(ADDR_COMMAND, buffer, addr);
+                encode_with_header_prefix!(magic, ADDR_COMMAND, buffer, addr);
+            }
+            Self::AddrV2(addr) => {
+                encode_with_header_prefix!(magic, ADDRV2_COMMAND, buffer, addr);
+            }
+            Self::SendAddrV2 => {
+                encode_with_header_prefix!(magic, SENDADDRV2_COMMAND, buffer);
             }
             Self::GetHeaders(locator_hashes) => {
-                encode_with_header_prefix!(GETHEADERS_COMMAND, buffer, locator_hashes);
+                encode_with_header_prefix!(magic, GETHEADERS_COMMAND, buffer, locator_hashes);
             }
             Self::Headers(headers) => {
-                encode_with_header_prefix!(HEADERS_COMMAND, buffer, headers);
+                encode_with_header_prefix!(magic, HEADERS_COMMAND, buffer, headers);
             }
             Self::GetBlocks(locator_hashes) => {
-                encode_with_header_prefix!(GETBLOCKS_COMMAND, buffer, locator_hashes);
+                encode_with_header_prefix!(magic, GETBLOCKS_COMMAND, buffer, locator_hashes);
             }
             Self::Block(block) => {
-                encode_with_header_prefix!(BLOCK_COMMAND, buffer, block);
+                encode_with_header_prefix!(magic, BLOCK_COMMAND, buffer, block);
             }
             Self::GetData(inv) => {
-                encode_with_header_prefix!(GETDATA_COMMAND, buffer, inv);
+                encode_with_header_prefix!(magic, GETDATA_COMMAND, buffer, inv);
             }
             Self::Inv(inv) => {
-                encode_with_header_prefix!(INV_COMMAND, buffer, inv);
+                encode_with_header_prefix!(magic, INV_COMMAND, buffer, inv);
             }
             Self::NotFound(inv) => {
-                encode_with_header_prefix!(NOTFOUND_COMMAND, buffer, inv);
+                encode_with_header_prefix!(magic, NOTFOUND_COMMAND, buffer, inv);
             }
             Self::MemPool => {
-                encode_with_header_prefix!(MEMPOOL_COMMAND, buffer);
+                encode_with_header_prefix!(magic, MEMPOOL_COMMAND, buffer);
             }
             Self::Tx(tx) => {
-                encode_with_header_prefix!(TX_COMMAND, buffer, tx);
+                encode_with_header_prefix!(magic, TX_COMMAND, buffer, tx);
             }
             Self::Reject(reject) => {
-                encode_with_header_prefix!(REJECT_COMMAND, buffer, reject);
+                encode_with_header_prefix!(magic, REJECT_COMMAND, buffer, reject);
             }
             Self::FilterLoad(filter_load) => {
-                encode_with_header_prefix!(FILTERLOAD_COMMAND, buffer, filter_load);
+                encode_with_header_prefix!(magic, FILTERLOAD_COMMAND, buffer, filter_load);
             }
             Self::FilterAdd(filter) => {
-                encode_with_header_prefix!(FILTERADD_COMMAND, buffer, filter);
+                encode_with_header_prefix!(magic, FILTERADD_COMMAND, buffer, filter);
             }
             Self::FilterClear => {
-                encode_with_header_prefix!(FILTERCLEAR_COMMAND, buffer);
+                encode_with_header_prefix!(magic, FILTERCLEAR_COMMAND, buffer);
             }
             // Don't send deprecated alert messages.
             Self::Alert => (),
@@ -191,6 +214,8 @@ impl Message {
             PONG_COMMAND => Self::Pong(Nonce::decode(bytes)?),
             GETADDR_COMMAND => Self::GetAddr,
             ADDR_COMMAND => Self::Addr(Addr::decode(bytes)?),
+            ADDRV2_COMMAND => Self::AddrV2(AddrV2::decode(bytes)?),
+            SENDADDRV2_COMMAND => Self::SendAddrV2,
             GETHEADERS_COMMAND => Self::GetHeaders(LocatorHashes::decode(bytes)?),
             HEADERS_COMMAND => Self::Headers(Headers::decode(bytes)?),
             GETBLOCKS_COMMAND => Self::GetBlocks(LocatorHashes::decode(bytes)?),
@@ -227,6 +252,8 @@ impl std::fmt::Display for Message {
             Message::Pong(nonce) => f.write_fmt(format_args!("Pong({nonce:?})")),
             Message::GetAddr => f.write_str("GetAddr"),
             Message::Addr(_) => f.write_str("Addr"),
+            Message::AddrV2(_) => f.write_str("AddrV2"),
+            Message::SendAddrV2 => f.write_str("SendAddrV2"),
             Message::GetHeaders(_) => f.write_str("GetHeaders"),
             Message::Headers(_) => f.write_str("Headers"),
             Message::GetBlocks(_) => f.write_str("GetBlocks"),
@@ -1,4 +1,5 @@
 //! An implementation of the Zcash network protocol types and messages.
 
 pub mod message;
+pub mod network;
 pub mod payload;
@@ -0,0 +1,104 @@
+//! Selection of the Zcash network to speak to at runtime.
+
+use std::{fmt, str::FromStr};
+
+use crate::protocol::message::constants::{
+    MAGIC_LEN, MAGIC_MAINNET, MAGIC_REGTEST, MAGIC_TESTNET, P2P_DEFAULT_MAINNET_PORT,
+    P2P_DEFAULT_REGTEST_PORT, P2P_DEFAULT_TESTNET_PORT,
+};
+
+/// A Zcash network, determines the message magic and the default port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    /// Returns the magic bytes prefixing every message header on this network.
+    pub fn magic(&self) -> [u8; MAGIC_LEN] {
+        match self {
+            Self::Mainnet => MAGIC_MAINNET,
+            Self::Testnet => MAGIC_TESTNET,
+            Self::Regtest => MAGIC_REGTEST,
+        }
+    }
+
+    /// Returns the default p2p port nodes on this network listen on.
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::Mainnet => P2P_DEFAULT_MAINNET_PORT,
+            Self::Testnet => P2P_DEFAULT_TESTNET_PORT,
+            Self::Regtest => P2P_DEFAULT_REGTEST_PORT,
+        }
+    }
+
+    /// Returns the network using the given magic bytes, if any.
+    pub fn from_magic(magic: [u8; MAGIC_LEN]) -> Option<Self> {
+        [Self::Mainnet, Self::Testnet, Self::Regtest]
+            .into_iter()
+            .find(|network| network.magic() == magic)
+    }
+}
+
+impl Default for Network {
+    /// Tests run against a testnet node, everything else defaults to mainnet.
+    fn default() -> Self {
+        if cfg!(test) {
+            Self::Testnet
+        } else {
+            Self::Mainnet
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mainnet => "mainnet",
+            Self::Testnet => "testnet",
+            Self::Regtest => "regtest",
+        })
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" | "main" => Ok(Self::Mainnet),
+            "testnet" | "test" => Ok(Self::Testnet),
+            "regtest" => Ok(Self::Regtest),
+            other => Err(format!(
+                "unknown network {other:?}, expected mainnet, testnet or regtest"
+            )),
+        }
+    }
+}
+
+/// The error returned when decoding a message whose header magic doesn't match the expected
+/// network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongMagic {
+    /// The network the decoder was set up for.
+    pub expected: Network,
+    /// The magic found in the header.
+    pub found: [u8; MAGIC_LEN],
+}
+
+impl fmt::Display for WrongMagic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Network::from_magic(self.found) {
+            Some(network) => write!(f, "expected {} magic, got {network}", self.expected),
+            None => write!(
+                f,
+                "expected {} magic, got {:02x?}",
+                self.expected, self.found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WrongMagic {}
@@ -9,7 +9,7 @@ use std::{
 use bytes::{Buf, BufMut};
 use time::OffsetDateTime;
 
-use crate::protocol::payload::{codec::Codec, read_n_bytes, read_short_timestamp};
+use crate::protocol::payload::{codec::Codec, read_n_bytes, read_short_timestamp, ServiceFlags};
 
 /// A list of network addresses, used for peering.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -51,7 +51,7 @@ pub struct NetworkAddr {
     /// Note: Present only when version is >= 31402
     pub last_seen: Option<OffsetDateTime>,
     /// The services supported by this address.
-    pub services: u64,
+    pub services: ServiceFlags,
     /// The socket address.
     pub addr: SocketAddr,
 }
@@ -63,13 +63,13 @@ impl NetworkAddr {
     pub fn new(addr: SocketAddr) -> Self {
         Self {
             last_seen: Some(OffsetDateTime::now_utc()),
-            services: 1,
+            services: ServiceFlags::NODE_NETWORK,
             addr,
         }
     }
 
     pub fn encode_without_timestamp<B: BufMut>(&self, buffer: &mut B) -> io::Result<()> {
-        buffer.put_u64_le(self.services);
+        self.services.encode(buffer)?;
 
         let (ip, port) = match self.addr {
             SocketAddr::V4(v4) => (v4.ip().to_ipv6_mapped(), v4.port()),
@@ -83,7 +83,7 @@ impl NetworkAddr {
     }
 
     pub(super) fn decode_without_timestamp<B: Buf>(bytes: &mut B) -> io::Result<Self> {
-        let services = u64::from_le_bytes(read_n_bytes(bytes)?);
+        let services = ServiceFlags::decode(bytes)?;
 
         if bytes.remaining() < 16 {
             return Err(io::ErrorKind::InvalidData.into());
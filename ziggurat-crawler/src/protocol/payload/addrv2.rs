@@ -0,0 +1,397 @@
+//! Network address types introduced by [ZIP-155](https://zips.z.cash/zip-0155).
+//!
+//! ZIP-155 adopts the `addrv2` encoding from [BIP-155](https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki),
+//! which can express addresses that do not fit into the 16 bytes of a legacy [`NetworkAddr`](super::addr::NetworkAddr),
+//! such as Tor v3 and I2P.
+
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::{Buf, BufMut};
+use sha3::{Digest, Sha3_256};
+use time::OffsetDateTime;
+
+use crate::protocol::payload::{
+    codec::Codec, read_n_bytes, read_short_timestamp, ServiceFlags, VarInt,
+};
+
+/// The maximum length of an address in an `addrv2` entry, as defined by BIP-155.
+pub const MAX_ADDRV2_LEN: usize = 512;
+
+/// A list of ZIP-155 network addresses, used for peering.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AddrV2 {
+    pub addrs: Vec<NetworkAddrV2>,
+}
+
+impl AddrV2 {
+    /// Returns an `AddrV2` with no addresses.
+    pub fn empty() -> Self {
+        Self { addrs: Vec::new() }
+    }
+
+    /// Returns an `AddrV2` with the given addresses.
+    pub fn new(addrs: Vec<NetworkAddrV2>) -> Self {
+        Self { addrs }
+    }
+
+    /// Returns an iterator over the list of network addresses.
+    pub fn iter(&self) -> std::slice::Iter<NetworkAddrV2> {
+        self.addrs.iter()
+    }
+}
+
+impl Codec for AddrV2 {
+    fn encode<B: BufMut>(&self, buffer: &mut B) -> io::Result<()> {
+        self.addrs.encode(buffer)
+    }
+
+    fn decode<B: Buf>(bytes: &mut B) -> io::Result<Self> {
+        Ok(Self::new(Vec::decode(bytes)?))
+    }
+}
+
+/// The BIP-155 network IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkId {
+    Ipv4,
+    Ipv6,
+    TorV2,
+    TorV3,
+    I2p,
+    Cjdns,
+}
+
+impl NetworkId {
+    /// Returns the serialized network ID.
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Ipv4 => 1,
+            Self::Ipv6 => 2,
+            Self::TorV2 => 3,
+            Self::TorV3 => 4,
+            Self::I2p => 5,
+            Self::Cjdns => 6,
+        }
+    }
+
+    /// Returns the network ID for the serialized value, `None` if it is unknown.
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Ipv4),
+            2 => Some(Self::Ipv6),
+            3 => Some(Self::TorV2),
+            4 => Some(Self::TorV3),
+            5 => Some(Self::I2p),
+            6 => Some(Self::Cjdns),
+            _ => None,
+        }
+    }
+
+    /// Returns the address length mandated for this network.
+    pub fn addr_len(&self) -> usize {
+        match self {
+            Self::Ipv4 => 4,
+            Self::Ipv6 | Self::Cjdns => 16,
+            Self::TorV2 => 10,
+            Self::TorV3 | Self::I2p => 32,
+        }
+    }
+}
+
+/// An address on one of the BIP-155 networks.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AddrV2Address {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// The 80-bit onion service identifier (deprecated by Tor, still relayed by some nodes).
+    TorV2([u8; 10]),
+    /// The ed25519 public key of the onion service.
+    TorV3([u8; 32]),
+    /// The SHA-256 hash of the I2P destination.
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+    /// An address with a network ID this implementation doesn't know about, kept so it can be
+    /// relayed unchanged.
+    Unknown(u8, Vec<u8>),
+}
+
+impl AddrV2Address {
+    /// Returns the serialized network ID of the address.
+    pub fn network_code(&self) -> u8 {
+        match self {
+            Self::Ipv4(_) => NetworkId::Ipv4.code(),
+            Self::Ipv6(_) => NetworkId::Ipv6.code(),
+            Self::TorV2(_) => NetworkId::TorV2.code(),
+            Self::TorV3(_) => NetworkId::TorV3.code(),
+            Self::I2p(_) => NetworkId::I2p.code(),
+            Self::Cjdns(_) => NetworkId::Cjdns.code(),
+            Self::Unknown(code, _) => *code,
+        }
+    }
+
+    /// Returns the network ID of the address, `None` if it is unknown.
+    pub fn network_id(&self) -> Option<NetworkId> {
+        NetworkId::from_code(self.network_code())
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ipv4(ip) => ip.octets().to_vec(),
+            Self::Ipv6(ip) | Self::Cjdns(ip) => ip.octets().to_vec(),
+            Self::TorV2(b) => b.to_vec(),
+            Self::TorV3(b) | Self::I2p(b) => b.to_vec(),
+            Self::Unknown(_, b) => b.clone(),
+        }
+    }
+
+    fn from_bytes(code: u8, bytes: Vec<u8>) -> io::Result<Self> {
+        let id = match NetworkId::from_code(code) {
+            Some(id) => id,
+            None => return Ok(Self::Unknown(code, bytes)),
+        };
+
+        if bytes.len() != id.addr_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("addrv2 {id:?} address of length {}", bytes.len()),
+            ));
+        }
+
+        let addr = match id {
+            NetworkId::Ipv4 => Self::Ipv4(Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[..]).unwrap())),
+            NetworkId::Ipv6 => {
+                Self::Ipv6(Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..]).unwrap()))
+            }
+            NetworkId::TorV2 => Self::TorV2(bytes[..].try_into().unwrap()),
+            NetworkId::TorV3 => Self::TorV3(bytes[..].try_into().unwrap()),
+            NetworkId::I2p => Self::I2p(bytes[..].try_into().unwrap()),
+            NetworkId::Cjdns => {
+                Self::Cjdns(Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..]).unwrap()))
+            }
+        };
+
+        Ok(addr)
+    }
+}
+
+impl fmt::Display for AddrV2Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipv4(ip) => write!(f, "{ip}"),
+            Self::Ipv6(ip) | Self::Cjdns(ip) => write!(f, "[{ip}]"),
+            Self::TorV2(id) => write!(f, "{}.onion", base32(id)),
+            Self::TorV3(pubkey) => {
+                // https://gitweb.torproject.org/torspec.git/tree/rend-spec-v3.txt#n2135
+                const VERSION: u8 = 3;
+                let mut hasher = Sha3_256::new();
+                hasher.update(b".onion checksum");
+                hasher.update(pubkey);
+                hasher.update([VERSION]);
+                let checksum = hasher.finalize();
+
+                let mut raw = pubkey.to_vec();
+                raw.extend_from_slice(&checksum[..2]);
+                raw.push(VERSION);
+                write!(f, "{}.onion", base32(&raw))
+            }
+            Self::I2p(hash) => write!(f, "{}.b32.i2p", base32(hash)),
+            Self::Unknown(code, bytes) => write!(f, "unknown-{code}-{}", hex::encode(bytes)),
+        }
+    }
+}
+
+/// A ZIP-155 network address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddrV2 {
+    /// The last time this address was seen.
+    pub last_seen: OffsetDateTime,
+    /// The services supported by this address.
+    pub services: ServiceFlags,
+    /// The address, tagged with its network.
+    pub addr: AddrV2Address,
+    /// The port.
+    pub port: u16,
+}
+
+impl NetworkAddrV2 {
+    /// Creates a new `NetworkAddrV2` from a socket address, with `last_seen=OffsetDateTime::now_utc()`
+    /// and `services=1` (only `NODE_NETWORK` is enabled).
+    pub fn new(addr: SocketAddr) -> Self {
+        let (addr, port) = match addr {
+            SocketAddr::V4(v4) => (AddrV2Address::Ipv4(*v4.ip()), v4.port()),
+            SocketAddr::V6(v6) => (AddrV2Address::Ipv6(*v6.ip()), v6.port()),
+        };
+
+        Self {
+            last_seen: OffsetDateTime::now_utc(),
+            services: ServiceFlags::NODE_NETWORK,
+            addr,
+            port,
+        }
+    }
+
+    /// Returns the socket address if the address can be reached over plain IP.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.addr {
+            AddrV2Address::Ipv4(ip) => Some(SocketAddr::new(IpAddr::V4(ip), self.port)),
+            AddrV2Address::Ipv6(ip) => Some(SocketAddr::new(IpAddr::V6(ip), self.port)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NetworkAddrV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.addr, self.port)
+    }
+}
+
+impl Codec for NetworkAddrV2 {
+    fn encode<B: BufMut>(&self, buffer: &mut B) -> io::Result<()> {
+        let timestamp: u32 = self.last_seen.unix_timestamp().try_into().unwrap();
+        buffer.put_u32_le(timestamp);
+
+        write_compact_size(self.services.bits(), buffer);
+
+        let bytes = self.addr.bytes();
+        buffer.put_u8(self.addr.network_code());
+        VarInt::new(bytes.len()).encode(buffer)?;
+        buffer.put_slice(&bytes);
+        buffer.put_u16(self.port);
+
+        Ok(())
+    }
+
+    fn decode<B: Buf>(bytes: &mut B) -> io::Result<Self> {
+        let last_seen = read_short_timestamp(bytes)?;
+        let services = ServiceFlags::from(read_compact_size(bytes)?);
+
+        let code = u8::from_le_bytes(read_n_bytes(bytes)?);
+        let len = *VarInt::decode(bytes)?;
+        if len > MAX_ADDRV2_LEN || bytes.remaining() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("addrv2 address length of {len} is invalid"),
+            ));
+        }
+        let mut raw = vec![0u8; len];
+        bytes.copy_to_slice(&mut raw);
+        let addr = AddrV2Address::from_bytes(code, raw)?;
+
+        let port = u16::from_be_bytes(read_n_bytes(bytes)?);
+
+        Ok(Self {
+            last_seen,
+            services,
+            addr,
+            port,
+        })
+    }
+}
+
+// Services are a CompactSize in `addrv2`, which `VarInt` can't be used for since it caps values
+// at the maximum message length.
+fn write_compact_size<B: BufMut>(value: u64, buffer: &mut B) {
+    match value {
+        0..=0xfc => buffer.put_u8(value as u8),
+        0xfd..=0xffff => {
+            buffer.put_u8(0xfd);
+            buffer.put_u16_le(value as u16);
+        }
+        0x1_0000..=0xffff_ffff => {
+            buffer.put_u8(0xfe);
+            buffer.put_u32_le(value as u32);
+        }
+        _ => {
+            buffer.put_u8(0xff);
+            buffer.put_u64_le(value);
+        }
+    }
+}
+
+fn read_compact_size<B: Buf>(bytes: &mut B) -> io::Result<u64> {
+    let value = match u8::from_le_bytes(read_n_bytes(bytes)?) {
+        flag @ 0x00..=0xfc => flag as u64,
+        0xfd => u16::from_le_bytes(read_n_bytes(bytes)?) as u64,
+        0xfe => u32::from_le_bytes(read_n_bytes(bytes)?) as u64,
+        0xff => u64::from_le_bytes(read_n_bytes(bytes)?),
+    };
+
+    Ok(value)
+}
+
+/// Lowercase RFC 4648 base32 without padding, as used by Tor and I2P.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut out = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn addrv2_roundtrip() {
+        let mut tor = NetworkAddrV2::new("1.2.3.4:8233".parse().unwrap());
+        tor.addr = AddrV2Address::TorV3([7; 32]);
+        let original = AddrV2::new(vec![
+            NetworkAddrV2::new("1.2.3.4:8233".parse().unwrap()),
+            NetworkAddrV2::new("[2001:db8::1]:8233".parse().unwrap()),
+            tor,
+        ]);
+
+        let mut buffer = Vec::new();
+        original.encode(&mut buffer).unwrap();
+
+        let decoded = AddrV2::decode(&mut Cursor::new(&buffer[..])).unwrap();
+        // Timestamps are encoded with second precision.
+        assert_eq!(decoded.addrs.len(), original.addrs.len());
+        for (d, o) in decoded.iter().zip(original.iter()) {
+            assert_eq!((&d.addr, d.port, d.services), (&o.addr, o.port, o.services));
+        }
+        assert_eq!(
+            decoded.addrs[0].socket_addr(),
+            Some("1.2.3.4:8233".parse().unwrap())
+        );
+        assert_eq!(decoded.addrs[2].socket_addr(), None);
+    }
+
+    #[test]
+    fn addrv2_rejects_bad_length() {
+        let mut buffer = Vec::new();
+        buffer.put_u32_le(0);
+        VarInt::new(1).encode(&mut buffer).unwrap();
+        buffer.put_u8(NetworkId::Ipv4.code());
+        VarInt::new(5).encode(&mut buffer).unwrap();
+        buffer.put_slice(&[1, 2, 3, 4, 5]);
+        buffer.put_u16(8233);
+
+        assert!(NetworkAddrV2::decode(&mut Cursor::new(&buffer[..])).is_err());
+    }
+
+    #[test]
+    fn base32_matches_rfc4648() {
+        assert_eq!(base32(b"foobar"), "mzxw6ytboi");
+    }
+}
@@ -1,6 +1,6 @@
 //! Network message payload types.
 
-use std::io;
+use std::{fmt, io, str::FromStr};
 
 use bytes::{Buf, BufMut};
 use rand::{thread_rng, Rng};
@@ -10,6 +10,9 @@ use time::OffsetDateTime;
 pub mod addr;
 pub use addr::Addr;
 
+pub mod addrv2;
+pub use addrv2::AddrV2;
+
 pub mod block;
 
 pub mod inv;
@@ -24,6 +27,9 @@ pub use version::Version;
 pub mod reject;
 pub use reject::Reject;
 
+pub mod services;
+pub use services::ServiceFlags;
+
 use self::codec::Codec;
 use crate::protocol::message::constants::{MAX_MESSAGE_LEN, PROTOCOL_VERSION};
 
@@ -36,6 +42,13 @@ pub use filter::{FilterAdd, FilterLoad};
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct Nonce(u64);
 
+impl Nonce {
+    /// Returns the raw nonce value.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
 impl Default for Nonce {
     fn default() -> Self {
         Self(thread_rng().gen())
@@ -187,7 +200,7 @@ impl VarStr {
 }
 
 /// A general purpose hash of length `32`.
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
 pub struct Hash([u8; 32]);
 
 impl Hash {
@@ -200,6 +213,34 @@ impl Hash {
     pub fn zeroed() -> Self {
         Self([0; 32])
     }
+
+    /// Returns the raw bytes, in wire order.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Displays the hash as hex in the byte-reversed order block explorers and RPCs use.
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self.0;
+        bytes.reverse();
+        f.write_str(&hex::encode(bytes))
+    }
+}
+
+/// Parses the byte-reversed hex form produced by [`Display`](fmt::Display).
+impl FromStr for Hash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes: [u8; 32] = hex::decode(s)
+            .map_err(|e| e.to_string())?
+            .try_into()
+            .map_err(|_| format!("expected 32 bytes of hex, got {:?}", s))?;
+        bytes.reverse();
+        Ok(Self(bytes))
+    }
 }
 
 impl Codec for Hash {
@@ -0,0 +1,111 @@
+//! Service bits advertised in `version` messages and in address gossip.
+
+use std::{fmt, io};
+
+use bitflags::{bitflags, Flags};
+use bytes::{Buf, BufMut};
+
+use crate::protocol::payload::{codec::Codec, read_n_bytes};
+
+bitflags! {
+    /// The services a node advertises.
+    ///
+    /// Bits without a name are preserved, so a value always round-trips through the codec.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct ServiceFlags: u64 {
+        /// The node serves the full block chain.
+        const NODE_NETWORK = 1;
+        /// The node answers `getutxo` requests (BIP-64).
+        const NODE_GETUTXO = 1 << 1;
+        /// The node supports bloom filtered connections (BIP-111).
+        const NODE_BLOOM = 1 << 2;
+        /// The node serves witness data (BIP-144), not used by Zcash.
+        const NODE_WITNESS = 1 << 3;
+        /// The node serves compact block filters (BIP-157).
+        const NODE_COMPACT_FILTERS = 1 << 6;
+        /// The node serves only recent blocks (BIP-159).
+        const NODE_NETWORK_LIMITED = 1 << 10;
+
+        const _ = !0;
+    }
+}
+
+impl ServiceFlags {
+    /// Returns the names of the set flags, unnamed bits are given as `BIT_<n>`.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .iter_names()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let unknown = self.bits() & !Self::all_named().bits();
+        names.extend(
+            (0..64)
+                .filter(|i| unknown & (1u64 << i) != 0)
+                .map(|i| format!("BIT_{i}")),
+        );
+        names
+    }
+
+    fn all_named() -> Self {
+        Self::FLAGS
+            .iter()
+            .filter(|f| f.is_named())
+            .fold(Self::empty(), |acc, f| acc | *f.value())
+    }
+}
+
+impl From<u64> for ServiceFlags {
+    fn from(bits: u64) -> Self {
+        Self::from_bits_retain(bits)
+    }
+}
+
+impl From<ServiceFlags> for u64 {
+    fn from(flags: ServiceFlags) -> Self {
+        flags.bits()
+    }
+}
+
+impl fmt::Display for ServiceFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("NONE");
+        }
+        f.write_str(&self.names().join(" | "))
+    }
+}
+
+impl Codec for ServiceFlags {
+    fn encode<B: BufMut>(&self, buffer: &mut B) -> io::Result<()> {
+        buffer.put_u64_le(self.bits());
+
+        Ok(())
+    }
+
+    fn decode<B: Buf>(bytes: &mut B) -> io::Result<Self> {
+        Ok(Self::from_bits_retain(u64::from_le_bytes(read_n_bytes(
+            bytes,
+        )?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_known_and_unknown_bits() {
+        let flags = ServiceFlags::from(1 | 4 | (1 << 40));
+        assert_eq!(flags.names(), vec!["NODE_NETWORK", "NODE_BLOOM", "BIT_40"]);
+        assert_eq!(flags.to_string(), "NODE_NETWORK | NODE_BLOOM | BIT_40");
+        assert_eq!(ServiceFlags::empty().to_string(), "NONE");
+    }
+
+    #[test]
+    fn codec_keeps_unknown_bits() {
+        let flags = ServiceFlags::NODE_NETWORK_LIMITED | ServiceFlags::from(1 << 63);
+        let mut buf = Vec::new();
+        flags.encode(&mut buf).unwrap();
+        assert_eq!(ServiceFlags::decode(&mut &buf[..]).unwrap(), flags);
+    }
+}
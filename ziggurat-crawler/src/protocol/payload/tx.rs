@@ -34,6 +34,17 @@ impl Tx {
         Ok(hash)
     }
 
+    /// Returns the transaction format version, `1` to `5`.
+    pub fn version(&self) -> u32 {
+        match self {
+            Tx::V1(_) => 1,
+            Tx::V2(_) => 2,
+            Tx::V3(_) => 3,
+            Tx::V4(_) => 4,
+            Tx::V5(_) => 5,
+        }
+    }
+
     /// Convenience function which creates the [`InvHash`] for this `Tx`.
     pub fn inv_hash(&self) -> InvHash {
         InvHash::Tx(self.double_sha256().unwrap())
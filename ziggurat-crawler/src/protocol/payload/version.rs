@@ -7,9 +7,10 @@ use time::OffsetDateTime;
 
 use crate::protocol::{
     message::constants::USER_AGENT,
+    network::Network,
     payload::{
         addr::NetworkAddr, codec::Codec, read_n_bytes, read_timestamp, Nonce, ProtocolVersion,
-        VarStr,
+        ServiceFlags, VarStr,
     },
 };
 
@@ -19,7 +20,7 @@ pub struct Version {
     /// The protocol version of the sender.
     pub version: ProtocolVersion,
     /// The services supported by the sender.
-    pub services: u64,
+    pub services: ServiceFlags,
     /// The timestamp of the message.
     pub timestamp: OffsetDateTime,
     /// The receiving address of the message.
@@ -37,22 +38,30 @@ pub struct Version {
 }
 
 impl Version {
-    /// Constructs a `Version`, where `addr_recv` is the remote `zcashd`/`zebra` node address and
-    /// `addr_from` is our local node address.
-    pub fn new(addr_recv: SocketAddr, addr_from: SocketAddr) -> Self {
+    /// Constructs a `Version` for the given network, where `addr_recv` is the remote
+    /// `zcashd`/`zebra` node address and `addr_from` is our local node address. Addresses without
+    /// a port (port `0`) are sent with the network's default port.
+    pub fn new(network: Network, addr_recv: SocketAddr, addr_from: SocketAddr) -> Self {
+        let with_port = |mut addr: SocketAddr| {
+            if addr.port() == 0 {
+                addr.set_port(network.default_port());
+            }
+            addr
+        };
+
         Self {
             version: ProtocolVersion::current(),
-            services: 1,
+            services: ServiceFlags::NODE_NETWORK,
             timestamp: OffsetDateTime::now_utc(),
             addr_recv: NetworkAddr {
                 last_seen: None,
-                services: 1,
-                addr: addr_recv,
+                services: ServiceFlags::NODE_NETWORK,
+                addr: with_port(addr_recv),
             },
             addr_from: NetworkAddr {
                 last_seen: None,
-                services: 1,
-                addr: addr_from,
+                services: ServiceFlags::NODE_NETWORK,
+                addr: with_port(addr_from),
             },
             nonce: Nonce::default(),
             // Let's pretend to be a ZCashd node 5.4.2
@@ -72,7 +81,7 @@ impl Version {
 impl Codec for Version {
     fn encode<B: BufMut>(&self, buffer: &mut B) -> io::Result<()> {
         self.version.encode(buffer)?;
-        buffer.put_u64_le(self.services);
+        self.services.encode(buffer)?;
         buffer.put_i64_le(self.timestamp.unix_timestamp());
 
         self.addr_recv.encode_without_timestamp(buffer)?;
@@ -88,7 +97,7 @@ impl Codec for Version {
 
     fn decode<B: Buf>(bytes: &mut B) -> io::Result<Self> {
         let version = ProtocolVersion::decode(bytes)?;
-        let services = u64::from_le_bytes(read_n_bytes(bytes)?);
+        let services = ServiceFlags::decode(bytes)?;
         let timestamp = read_timestamp(bytes)?;
 
         let addr_recv = NetworkAddr::decode_without_timestamp(bytes)?;
@@ -113,3 +122,23 @@ impl Codec for Version {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_the_network_port() {
+        let listen: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let peer: SocketAddr = ([10, 0, 0, 1], 8233).into();
+        let mut version = Version::new(Network::Testnet, peer, listen);
+        // the wire only carries whole seconds
+        version.timestamp = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        assert_eq!(version.addr_recv.addr, peer);
+        assert_eq!(version.addr_from.addr.port(), 18233);
+
+        let mut buf = Vec::new();
+        version.encode(&mut buf).unwrap();
+        assert_eq!(Version::decode(&mut &buf[..]).unwrap(), version);
+    }
+}
@@ -7,6 +7,7 @@ use std::io;
 use crate::{
     protocol::{
         message::Message,
+        network::Network,
         payload::{
             block::{Block, LocatorHashes},
             Addr, Hash, Inv, Nonce, Version,
@@ -151,7 +152,11 @@ mod when_node_receives_connection {
         // Send Version.
         synthetic_node.unicast(
             node.addr(),
-            Message::Version(Version::new(synthetic_node.listening_addr(), node.addr())),
+            Message::Version(Version::new(
+                Network::default(),
+                synthetic_node.listening_addr(),
+                node.addr(),
+            )),
         )?;
 
         // Read Version.
@@ -331,7 +336,11 @@ mod when_node_initiates_connection {
         // Send Version.
         synthetic_node.unicast(
             node_addr,
-            Message::Version(Version::new(synthetic_node.listening_addr(), node_addr)),
+            Message::Version(Version::new(
+                Network::default(),
+                synthetic_node.listening_addr(),
+                node_addr,
+            )),
         )?;
 
         // Read Version.
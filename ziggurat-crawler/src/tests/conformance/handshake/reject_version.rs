@@ -3,6 +3,7 @@ use assert_matches::assert_matches;
 use crate::{
     protocol::{
         message::Message,
+        network::Network,
         payload::{reject::CCode, Version},
     },
     setup::node::{Action, Node},
@@ -38,7 +39,11 @@ async fn c007_PING_reusing_nonce() {
     let nonce = assert_matches!(version, Message::Version(version) => version.nonce);
 
     // Send a Version.
-    let mut bad_version = Version::new(node.addr(), synthetic_node.listening_addr());
+    let mut bad_version = Version::new(
+        Network::default(),
+        node.addr(),
+        synthetic_node.listening_addr(),
+    );
     bad_version.nonce = nonce;
     synthetic_node
         .unicast(source, Message::Version(bad_version))
@@ -84,8 +89,12 @@ async fn c008_VERSION_with_obsolete_number() {
             .unicast(
                 node.addr(),
                 Message::Version(
-                    Version::new(node.addr(), synthetic_node.listening_addr())
-                        .with_version(obsolete_version_number),
+                    Version::new(
+                        Network::default(),
+                        node.addr(),
+                        synthetic_node.listening_addr(),
+                    )
+                    .with_version(obsolete_version_number),
                 ),
             )
             .unwrap();
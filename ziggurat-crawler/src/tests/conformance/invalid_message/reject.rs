@@ -15,6 +15,7 @@ use std::io;
 use crate::{
     protocol::{
         message::Message,
+        network::Network,
         payload::{block::Block, reject::CCode, FilterAdd, FilterLoad, Inv, Version},
     },
     setup::node::{Action, Node},
@@ -30,6 +31,7 @@ async fn c009_t1_VERSION_post_handshake() {
     // zcashd: pass
     // zebra:  fail (connection terminated)
     let version = Message::Version(Version::new(
+        Network::default(),
         "0.0.0.0:0".parse().unwrap(),
         "0.0.0.0:0".parse().unwrap(),
     ));
@@ -10,6 +10,7 @@ use tracing::*;
 use ziggurat_zcash::{
     protocol::{
         message::Message,
+        network::Network,
         payload::{block::Headers, Addr, Version},
     },
     tools::synthetic_node::MessageCodec,
@@ -113,7 +114,11 @@ impl Handshake for Crawler {
         let own_listening_addr: SocketAddr = ([127, 0, 0, 1], 0).into();
         let mut framed_stream = Framed::new(self.borrow_stream(&mut conn), MessageCodec::default());
 
-        let own_version = Message::Version(Version::new(conn_addr, own_listening_addr));
+        let own_version = Message::Version(Version::new(
+            Network::default(),
+            conn_addr,
+            own_listening_addr,
+        ));
         framed_stream.send(own_version).await?;
 
         // Here should be waiting for remote version message but as some nodes don't send it
@@ -176,7 +181,7 @@ impl Reading for Crawler {
                 if let Some(known_node) = self.known_network.nodes.write().get_mut(&source) {
                     known_node.protocol_version = Some(ver.version);
                     known_node.user_agent = Some(ver.user_agent);
-                    known_node.services = Some(ver.services);
+                    known_node.services = Some(ver.services.bits());
                     known_node.start_height = Some(ver.start_height);
                 }
 
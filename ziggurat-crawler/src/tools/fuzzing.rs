@@ -15,6 +15,7 @@ use rand_chacha::ChaCha8Rng;
 
 use crate::protocol::{
     message::{constants::*, Message, MessageHeader},
+    network::Network,
     payload::{
         block::{Headers, LocatorHashes},
         codec::Codec,
@@ -59,6 +60,7 @@ pub fn seeded_rng() -> ChaCha8Rng {
 pub fn default_fuzz_messages() -> Vec<Message> {
     vec![
         Message::Version(Version::new(
+            Network::default(),
             SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
             SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         )),
@@ -26,6 +26,7 @@ use tracing::*;
 use crate::{
     protocol::{
         message::{Message, MessageHeader},
+        network::{Network, WrongMagic},
         payload::{codec::Codec, Nonce, Version},
     },
     tools::message_filter::{Filter, MessageFilter},
@@ -476,11 +477,24 @@ impl Pea2Pea for InnerNode {
 // TODO: move to protocol
 pub struct MessageCodec {
     codec: LengthDelimitedCodec,
+    network: Network,
+}
+
+impl MessageCodec {
+    /// Creates a codec which speaks the given network, messages carrying another network's magic
+    /// fail to decode with a [`WrongMagic`] error.
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for MessageCodec {
     fn default() -> Self {
         Self {
+            network: Network::default(),
             codec: LengthDelimitedCodec::builder()
                 .length_adjustment(24)
                 .length_field_offset(16)
@@ -506,6 +520,15 @@ impl Decoder for MessageCodec {
         };
 
         let header = MessageHeader::decode(&mut bytes)?;
+        if header.magic != self.network.magic() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                WrongMagic {
+                    expected: self.network,
+                    found: header.magic,
+                },
+            ));
+        }
         let message = Message::decode(header.command, &mut bytes)?;
 
         Ok(Some(message))
@@ -526,7 +549,7 @@ impl Encoder<Message> for MessageCodec {
     type Error = io::Error;
 
     fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        message.encode(dst)
+        message.encode_with_magic(self.network.magic(), dst)
     }
 }
 
@@ -612,7 +635,11 @@ impl Handshake for InnerNode {
         match (self.handshake, node_conn_side) {
             (Some(HandshakeKind::Full), ConnectionSide::Initiator) => {
                 // Send and receive Version.
-                let own_version = Message::Version(Version::new(conn_addr, own_listening_addr));
+                let own_version = Message::Version(Version::new(
+                    Network::default(),
+                    conn_addr,
+                    own_listening_addr,
+                ));
                 framed_stream.send(own_version).await?;
 
                 let peer_version = framed_stream.try_next().await?;
@@ -660,7 +687,11 @@ impl Handshake for InnerNode {
                     None => return Err(io::ErrorKind::InvalidData.into()),
                 };
 
-                let own_version = Message::Version(Version::new(node_addr, own_listening_addr));
+                let own_version = Message::Version(Version::new(
+                    Network::default(),
+                    node_addr,
+                    own_listening_addr,
+                ));
                 framed_stream.send(own_version).await?;
 
                 // Receive and send Verack.
@@ -670,7 +701,11 @@ impl Handshake for InnerNode {
                 framed_stream.send(Message::Verack).await?;
             }
             (Some(HandshakeKind::VersionOnly), ConnectionSide::Initiator) => {
-                let own_version = Message::Version(Version::new(conn_addr, own_listening_addr));
+                let own_version = Message::Version(Version::new(
+                    Network::default(),
+                    conn_addr,
+                    own_listening_addr,
+                ));
                 framed_stream.send(own_version).await?;
 
                 let peer_version = framed_stream.try_next().await?;
@@ -707,7 +742,11 @@ impl Handshake for InnerNode {
                     None => return Err(io::ErrorKind::InvalidData.into()),
                 };
 
-                let own_version = Message::Version(Version::new(node_addr, own_listening_addr));
+                let own_version = Message::Version(Version::new(
+                    Network::default(),
+                    node_addr,
+                    own_listening_addr,
+                ));
                 framed_stream.send(own_version).await?;
             }
             (None, _) => {}